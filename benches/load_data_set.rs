@@ -0,0 +1,63 @@
+// Compares IDX loading strategies on the MNIST training set:
+// - The original per-byte loader, which reads an unbuffered File one byte at a time and calls
+//   Image::add for every pixel. It is reproduced here as the reference point.
+// - Buffered bulk loading.
+// - Memory-mapped bulk loading.
+//
+// The per-byte loader does not understand gzip, so the data files must be uncompressed.
+// Run with `cargo bench --bench load_data_set -- data_dir=<path>`.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use distance_research::data_location::resolve_data_dir;
+use distance_research::idx::{existing_path, LoadMode};
+use distance_research::mnist_data::{Grid, Image, IMAGE_BYTES, image_file_name, init_from_files_with, label_file_name, required_files};
+use distance_research::timing::time_milliseconds;
+
+const PREFIX: &str = "train";
+const REPETITIONS: usize = 3;
+
+fn main() -> io::Result<()> {
+    let args: HashSet<String> = env::args().collect();
+    let data_dir = resolve_data_dir(&args, &required_files(&[PREFIX]))?;
+    let images = existing_path(&data_dir, &image_file_name(PREFIX)).unwrap();
+    let labels = existing_path(&data_dir, &label_file_name(PREFIX)).unwrap();
+
+    let per_byte = report("per-byte Image::add", || per_byte_load(&images, &labels).unwrap().len());
+    let buffered = report("buffered bulk", || init_from_files_with(&images, &labels, LoadMode::Buffered).unwrap().len());
+    let mapped = report("memory-mapped bulk", || init_from_files_with(&images, &labels, LoadMode::MemoryMapped).unwrap().len());
+    println!("Speedup over per-byte: buffered {:.1}x, memory-mapped {:.1}x", per_byte / buffered, per_byte / mapped);
+    Ok(())
+}
+
+fn report<F: FnMut() -> usize>(label: &str, mut f: F) -> f64 {
+    let mut total = 0;
+    for _ in 0..REPETITIONS {
+        let (count, millis) = time_milliseconds(|| black_box(f()));
+        println!("{}: {} images in {} ms", label, count, millis);
+        total += millis;
+    }
+    let mean = total as f64 / REPETITIONS as f64;
+    println!("{}: mean {:.1} ms", label, mean);
+    mean.max(1.0)
+}
+
+#[allow(clippy::unbuffered_bytes)]
+fn per_byte_load(image_file_name: &Path, label_file_name: &Path) -> io::Result<Vec<(u8,Image)>> {
+    let labels: Vec<u8> = fs::File::open(label_file_name)?.bytes().skip(8).collect::<io::Result<_>>()?;
+    let mut images: Vec<(u8,Image)> = Vec::new();
+    let mut image = Image::new();
+    for b in fs::File::open(image_file_name)?.bytes().skip(16) {
+        image.add(b?);
+        if image.len() == IMAGE_BYTES {
+            images.push((labels[images.len()], image));
+            image = Image::new();
+        }
+    }
+    Ok(images)
+}
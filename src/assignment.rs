@@ -0,0 +1,132 @@
+//! One-to-one matching between keypoint sets. `kernel_points::closest_for_all` lets every point
+//! pick its nearest partner, even one that other points have already picked; here each point gets
+//! a partner of its own, chosen to minimize the total cost by the Hungarian algorithm.
+//!
+//! When the sets differ in size, every point left over after the smaller set is matched costs a
+//! fixed penalty, given as a distance in pixels.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PointMetric {
+    SquaredEuclidean, Manhattan
+}
+
+impl PointMetric {
+    pub fn cost(&self, p1: (usize,usize), p2: (usize,usize)) -> usize {
+        let (dx, dy) = (p1.0.abs_diff(p2.0), p1.1.abs_diff(p2.1));
+        match self {
+            PointMetric::SquaredEuclidean => dx * dx + dy * dy,
+            PointMetric::Manhattan => dx + dy
+        }
+    }
+
+    // The cost of a point this many pixels away along one axis.
+    pub fn cost_of_distance(&self, pixels: usize) -> usize {
+        self.cost((0, 0), (pixels, 0))
+    }
+}
+
+// For a square cost matrix, the column assigned to each row in a minimum-cost assignment, with
+// that cost. This is the O(n^3) shortest augmenting path form, with row and column potentials.
+pub fn hungarian(costs: &[Vec<usize>]) -> (Vec<usize>, usize) {
+    let n = costs.len();
+    assert!(costs.iter().all(|row| row.len() == n), "cost matrix must be square");
+    // Index 0 is a sentinel column; rows and columns are numbered from 1.
+    let (mut row_potential, mut column_potential) = (vec![0i64; n + 1], vec![0i64; n + 1]);
+    let mut row_of_column = vec![0; n + 1];
+    let mut previous_column = vec![0; n + 1];
+    for row in 1..=n {
+        row_of_column[0] = row;
+        let mut column = 0;
+        let mut slack = vec![i64::MAX; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[column] = true;
+            let current_row = row_of_column[column];
+            let (mut delta, mut next_column) = (i64::MAX, 0);
+            for j in 1..=n {
+                if !used[j] {
+                    let reduced = costs[current_row - 1][j - 1] as i64 - row_potential[current_row] - column_potential[j];
+                    if reduced < slack[j] {
+                        slack[j] = reduced;
+                        previous_column[j] = column;
+                    }
+                    if slack[j] < delta {
+                        delta = slack[j];
+                        next_column = j;
+                    }
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    row_potential[row_of_column[j]] += delta;
+                    column_potential[j] -= delta;
+                } else {
+                    slack[j] -= delta;
+                }
+            }
+            column = next_column;
+            if row_of_column[column] == 0 {
+                break;
+            }
+        }
+        while column != 0 {
+            let previous = previous_column[column];
+            row_of_column[column] = row_of_column[previous];
+            column = previous;
+        }
+    }
+    let mut assignment = vec![0; n];
+    for j in 1..=n {
+        assignment[row_of_column[j] - 1] = j - 1;
+    }
+    let total = assignment.iter().enumerate().map(|(i, j)| costs[i][*j]).sum();
+    (assignment, total)
+}
+
+// The cheapest one-to-one matching of the smaller set into the larger, plus unmatched_distance's
+// cost for each point of the larger set left over.
+pub fn assignment_distance(group1: &[(usize,usize)], group2: &[(usize,usize)], metric: PointMetric, unmatched_distance: usize) -> usize {
+    let size = group1.len().max(group2.len());
+    let penalty = metric.cost_of_distance(unmatched_distance);
+    let costs: Vec<Vec<usize>> = (0..size).map(|i| (0..size).map(|j| match (group1.get(i), group2.get(j)) {
+        (Some(p1), Some(p2)) => metric.cost(*p1, *p2),
+        _ => penalty
+    }).collect()).collect();
+    // The smaller set is padded with dummy points that cost the penalty whatever they match.
+    hungarian(&costs).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel_points::closest_for_all;
+
+    #[test]
+    fn test_hungarian() {
+        let costs = vec![vec![4, 1, 3], vec![2, 0, 5], vec![3, 2, 2]];
+        let (assignment, total) = hungarian(&costs);
+        assert_eq!(vec![1, 0, 2], assignment);
+        assert_eq!(5, total);
+        assert_eq!((vec![], 0), hungarian(&[]));
+    }
+
+    #[test]
+    fn test_one_to_one() {
+        // Both points of group2 are nearest to (0, 0), so greedy matching ignores (9, 0).
+        let group1 = vec![(0, 0), (9, 0)];
+        let group2 = vec![(1, 0), (2, 0)];
+        assert_eq!((1 + 49) + (1 + 4), closest_for_all(&group1, &group2));
+        assert_eq!(1 + 49, assignment_distance(&group1, &group2, PointMetric::SquaredEuclidean, 10));
+        assert_eq!(1 + 7, assignment_distance(&group1, &group2, PointMetric::Manhattan, 10));
+        assert_eq!(0, assignment_distance(&group1, &group1, PointMetric::SquaredEuclidean, 10));
+    }
+
+    #[test]
+    fn test_unmatched_penalty() {
+        let group1 = vec![(0, 0), (5, 5), (3, 1)];
+        let group2 = vec![(5, 6)];
+        assert_eq!(1 + 2 * 16, assignment_distance(&group1, &group2, PointMetric::SquaredEuclidean, 4));
+        assert_eq!(1 + 2 * 4, assignment_distance(&group2, &group1, PointMetric::Manhattan, 4));
+        assert_eq!(3 * 4, assignment_distance(&group1, &[], PointMetric::Manhattan, 4));
+    }
+}
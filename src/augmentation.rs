@@ -0,0 +1,228 @@
+//! Geometric transforms of `Image` for augmenting training sets, and a seeded pipeline that applies
+//! random combinations of them.
+//!
+//! Transforms other than integer translation map each output pixel back into the source image
+//! and sample it bilinearly. Anything that lands outside the source is background (0).
+//! Rotation, scaling, and shear are about the image center.
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::mnist_data::{Image, Grid, ImageIterator};
+use crate::pixel::clamp_to_u8;
+
+// Pixels outside the image count as 0, so edges fade into the background.
+pub fn bilinear(img: &Image, x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let value = |xi: f64, yi: f64| {
+        if xi < 0.0 || yi < 0.0 || xi >= img.width() as f64 || yi >= img.height() as f64 {
+            0.0
+        } else {
+            img.get(xi as usize, yi as usize) as f64
+        }
+    };
+    (1.0 - fx) * (1.0 - fy) * value(x0, y0) + fx * (1.0 - fy) * value(x0 + 1.0, y0)
+        + (1.0 - fx) * fy * value(x0, y0 + 1.0) + fx * fy * value(x0 + 1.0, y0 + 1.0)
+}
+
+// Builds an image of the same shape by sampling the source at source_point(x, y).
+pub fn resampled<F: Fn(f64, f64) -> (f64, f64)>(img: &Image, source_point: F) -> Image {
+    let mut result = Image::with_width(img.width());
+    ImageIterator::new(0, 0, img.width(), img.height(), 1).for_each(|(x, y)| {
+        let (sx, sy) = source_point(x as f64, y as f64);
+        result.add(clamp_to_u8(bilinear(img, sx, sy) as f32));
+    });
+    result
+}
+
+fn center(img: &Image) -> (f64, f64) {
+    ((img.width() as f64 - 1.0) / 2.0, (img.height() as f64 - 1.0) / 2.0)
+}
+
+// Applies the 2x2 matrix about the image center, then shifts by (dx, dy).
+pub fn affine(img: &Image, matrix: [[f64; 2]; 2], dx: f64, dy: f64) -> Image {
+    let [[a, b], [c, d]] = matrix;
+    let determinant = a * d - b * c;
+    assert!(determinant.abs() > f64::EPSILON, "affine matrix must be invertible");
+    let (cx, cy) = center(img);
+    resampled(img, |x, y| {
+        let (u, v) = (x - cx - dx, y - cy - dy);
+        (cx + (d * u - b * v) / determinant, cy + (a * v - c * u) / determinant)
+    })
+}
+
+pub fn translated(img: &Image, dx: isize, dy: isize) -> Image {
+    let mut result = Image::with_width(img.width());
+    ImageIterator::new(0, 0, img.width(), img.height(), 1).for_each(|(x, y)| {
+        let (sx, sy) = (x as isize - dx, y as isize - dy);
+        result.add(if img.in_bounds(sx, sy) {img.get(sx as usize, sy as usize)} else {0});
+    });
+    result
+}
+
+pub fn shifted(img: &Image, dx: f64, dy: f64) -> Image {
+    affine(img, [[1.0, 0.0], [0.0, 1.0]], dx, dy)
+}
+
+// Positive angles turn clockwise on screen, since y grows downward.
+pub fn rotated(img: &Image, degrees: f64) -> Image {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    affine(img, [[cos, -sin], [sin, cos]], 0.0, 0.0)
+}
+
+pub fn scaled(img: &Image, x_scale: f64, y_scale: f64) -> Image {
+    affine(img, [[x_scale, 0.0], [0.0, y_scale]], 0.0, 0.0)
+}
+
+// Horizontal shear: each row moves right by shear times its distance below the center.
+pub fn sheared(img: &Image, shear: f64) -> Image {
+    affine(img, [[1.0, shear], [0.0, 1.0]], 0.0, 0.0)
+}
+
+// Simard, Steinkraus, and Platt (2003): uniform random displacements in [-1, 1], smoothed by a
+// Gaussian of standard deviation sigma, then scaled by alpha.
+pub fn elastic_distorted(img: &Image, alpha: f64, sigma: f64, rng: &mut StdRng) -> Image {
+    let (width, height) = (img.width(), img.height());
+    let mut field = || {
+        let noise: Vec<f64> = (0..width * height).map(|_| rng.gen_range(-1.0..=1.0)).collect();
+        gaussian_smoothed(&noise, width, height, sigma)
+    };
+    let (dx, dy) = (field(), field());
+    resampled(img, |x, y| {
+        let i = y as usize * width + x as usize;
+        (x + alpha * dx[i], y + alpha * dy[i])
+    })
+}
+
+// Separable Gaussian blur of row-major values; samples beyond the edges count as 0.
+pub fn gaussian_smoothed(values: &[f64], width: usize, height: usize, sigma: f64) -> Vec<f64> {
+    if sigma <= 0.0 {
+        return values.to_vec();
+    }
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius).map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp()).collect();
+    let total: f64 = kernel.iter().sum();
+    let blur = |input: &[f64], horizontal: bool| -> Vec<f64> {
+        (0..width * height).map(|i| {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            (-radius..=radius).map(|offset| {
+                let (sx, sy) = if horizontal {(x + offset, y)} else {(x, y + offset)};
+                if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+                    0.0
+                } else {
+                    kernel[(offset + radius) as usize] * input[sy as usize * width + sx as usize]
+                }
+            }).sum::<f64>() / total
+        }).collect()
+    };
+    blur(&blur(values, true), false)
+}
+
+// Each augmented copy draws its parameters uniformly from these ranges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AugmentationPipeline {
+    pub max_shift: f64,
+    pub max_rotation_degrees: f64,
+    // Each axis scales independently by a factor in [1 - max_scale_change, 1 + max_scale_change].
+    pub max_scale_change: f64,
+    pub max_shear: f64,
+    // (alpha, sigma) for elastic distortion, if any.
+    pub elastic: Option<(f64, f64)>,
+    // The augmented set holds each original image followed by factor - 1 transformed copies.
+    pub factor: usize,
+    pub seed: u64
+}
+
+impl AugmentationPipeline {
+    // Moderate defaults for 28x28 digits, with Simard's elastic parameters.
+    pub fn new(factor: usize, seed: u64) -> Self {
+        AugmentationPipeline {max_shift: 2.0, max_rotation_degrees: 10.0, max_scale_change: 0.1, max_shear: 0.2,
+            elastic: Some((34.0, 4.0)), factor, seed}
+    }
+
+    pub fn augment(&self, images: &[(u8,Image)]) -> Vec<(u8,Image)> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut result = Vec::with_capacity(images.len() * self.factor);
+        for (label, img) in images.iter() {
+            result.push((*label, img.clone()));
+            for _ in 1..self.factor {
+                result.push((*label, self.transformed(img, &mut rng)));
+            }
+        }
+        result
+    }
+
+    // Rotation, scaling, shear, and subpixel shift are combined into one resampling.
+    pub fn transformed(&self, img: &Image, rng: &mut StdRng) -> Image {
+        let mut symmetric = |limit: f64| if limit > 0.0 {rng.gen_range(-limit..=limit)} else {0.0};
+        let (sin, cos) = symmetric(self.max_rotation_degrees).to_radians().sin_cos();
+        let (x_scale, y_scale) = (1.0 + symmetric(self.max_scale_change), 1.0 + symmetric(self.max_scale_change));
+        let shear = symmetric(self.max_shear);
+        let (dx, dy) = (symmetric(self.max_shift), symmetric(self.max_shift));
+        // Rotation * shear * scale.
+        let matrix = [[cos * x_scale, (cos * shear - sin) * y_scale], [sin * x_scale, (sin * shear + cos) * y_scale]];
+        let warped = affine(img, matrix, dx, dy);
+        match self.elastic {
+            Some((alpha, sigma)) => elastic_distorted(&warped, alpha, sigma, rng),
+            None => warped
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translations() {
+        let img = Image::test_pattern(5, 4);
+        let moved = translated(&img, 2, -1);
+        assert_eq!(img.get(0, 1), moved.get(2, 0));
+        assert_eq!(0, moved.get(1, 0));
+        assert_eq!(0, moved.get(4, 3));
+        assert_eq!(moved, shifted(&img, 2.0, -1.0));
+        let half = shifted(&img, 0.5, 0.0);
+        assert_eq!(clamp_to_u8((img.get(1, 2) as f32 + img.get(2, 2) as f32) / 2.0), half.get(2, 2));
+    }
+
+    #[test]
+    fn test_rotation_scale_shear() {
+        let img = Image::test_pattern(5, 5);
+        let quarter = rotated(&img, 90.0);
+        for (x, y) in img.x_y_iter() {
+            assert_eq!(img.get(y, 4 - x), quarter.get(x, y));
+        }
+        assert_eq!(img, rotated(&img, 0.0));
+        assert_eq!(img, scaled(&img, 1.0, 1.0));
+        let doubled = scaled(&img, 2.0, 1.0);
+        assert_eq!(img.get(2, 0), doubled.get(2, 0));
+        assert_eq!(img.get(3, 1), doubled.get(4, 1));
+        let slanted = sheared(&img, 1.0);
+        assert_eq!(img.get(2, 2), slanted.get(2, 2));
+        assert_eq!(img.get(2, 3), slanted.get(3, 3));
+    }
+
+    #[test]
+    fn test_elastic() {
+        let img = Image::test_pattern(8, 8);
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(img, elastic_distorted(&img, 0.0, 2.0, &mut rng));
+        let distorted = elastic_distorted(&img, 6.0, 1.0, &mut StdRng::seed_from_u64(1));
+        assert_ne!(img, distorted);
+        assert_eq!(distorted, elastic_distorted(&img, 6.0, 1.0, &mut StdRng::seed_from_u64(1)));
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let images = vec![(1, Image::test_pattern(10, 10)), (2, Image::test_pattern(10, 10).transposed())];
+        let pipeline = AugmentationPipeline::new(3, 5);
+        let augmented = pipeline.augment(&images);
+        assert_eq!(6, augmented.len());
+        assert_eq!(vec![1, 1, 1, 2, 2, 2], augmented.iter().map(|(label, _)| *label).collect::<Vec<_>>());
+        assert_eq!(images[1], augmented[3]);
+        assert_ne!(augmented[1].1, augmented[2].1);
+        assert_eq!(augmented, pipeline.augment(&images));
+        let still = AugmentationPipeline {max_shift: 0.0, max_rotation_degrees: 0.0, max_scale_change: 0.0, max_shear: 0.0, elastic: None, factor: 2, seed: 0};
+        assert_eq!(images[0].1, still.augment(&images)[1].1);
+    }
+}
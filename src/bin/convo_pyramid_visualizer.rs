@@ -1,6 +1,9 @@
 use bare_metal_modulo::{MNum, ModNum};
 use iced::{Element, Sandbox, Settings};
-use distance_research::mnist_data::{Image, load_data_set};
+use std::collections::HashSet;
+use std::env;
+use distance_research::mnist_data::{Image, load_data_set, required_files};
+use distance_research::data_location::resolve_data_dir;
 
 fn main() -> iced::Result {
     println!("Loading images....");
@@ -21,7 +24,9 @@ impl Sandbox for Visualizer {
     type Message = Message;
 
     fn new() -> Self {
-        let images = load_data_set("train").unwrap();
+        let args: HashSet<String> = env::args().collect();
+        let data_dir = resolve_data_dir(&args, &required_files(&["train"])).unwrap();
+        let images = load_data_set(&data_dir, "train").unwrap();
         let current = ModNum::new(0, images.len());
         Visualizer { images, current }
     }
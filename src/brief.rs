@@ -101,8 +101,8 @@ impl Descriptor {
     }
 
     fn apply<F: Fn(&Descriptor,&Image,usize,usize,usize,usize) -> bool>(&self, img: &Image, eval: &F) -> BitArray {
-        assert_eq!(img.side(), self.width());
-        assert_eq!(img.side(), self.height());
+        assert_eq!(img.width(), self.width());
+        assert_eq!(img.height(), self.height());
 
         let mut bits = BitArray::new();
         self.pairs.iter()
@@ -159,4 +159,31 @@ pub fn random_bounded_normal_value(dist: &Normal<f64>, start_value: usize, min:
     } else {
         max - 1
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{rectangular_image, RECT_WIDTH, RECT_HEIGHT};
+
+    #[test]
+    fn test_one_bit_per_pair() {
+        let img = rectangular_image();
+        let pixels = RECT_WIDTH * RECT_HEIGHT;
+        assert_eq!(100, Descriptor::classic_gaussian_brief(100, RECT_WIDTH, RECT_HEIGHT).apply_to(&img).len());
+        assert_eq!(100, Descriptor::classic_uniform_brief(100, RECT_WIDTH, RECT_HEIGHT).apply_to(&img).len());
+        assert_eq!(pixels * 2, Descriptor::uniform_neighbor(2, RECT_WIDTH, RECT_HEIGHT).apply_to(&img).len());
+        assert_eq!(pixels * 2, Descriptor::gaussian_neighbor(2, 5, RECT_WIDTH, RECT_HEIGHT).apply_to(&img).len());
+
+        let equidistant = Descriptor::equidistant(RECT_WIDTH, RECT_HEIGHT, 10, 6);
+        assert_eq!(pixels, equidistant.apply_to(&img).len());
+        assert_eq!(pixels, equidistant.apply_kernel(&img, 3).len());
+        assert_eq!(pixels, equidistant.majority_image(&img).len());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mismatched_shape() {
+        Descriptor::equidistant(RECT_HEIGHT, RECT_WIDTH, 6, 10).apply_to(&rectangular_image());
+    }
+}
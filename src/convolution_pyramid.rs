@@ -24,10 +24,13 @@
 // **Updated Idea** Just keep all the images from the previous levels.
 
 use crate::mnist_data::{Image, image_mean, Grid};
-use crate::convolutional::{extract_kernels_from, add_kernels_from_to};
+use crate::convolutional::{extract_kernels_from, add_kernels_from_to, strided};
 use crate::euclidean_distance::euclidean_distance;
+use crate::feature_cache::Cacheable;
 use hash_histogram::mode_values;
 use std::cmp::Ordering;
+use std::io;
+use std::io::{Read, Write};
 
 const KERNEL_SIZE: usize = 3;
 const STRIDE: usize = 2;
@@ -77,7 +80,8 @@ impl KernelPyramidImage {
     }
 
     pub fn distance(img1: &KernelPyramidImage, img2: &KernelPyramidImage) -> KernelPyramidDistance {
-        assert_eq!(img1.original.side(), img2.original.side());
+        assert_eq!(img1.original.width(), img2.original.width());
+        assert_eq!(img1.original.height(), img2.original.height());
         assert_eq!(img1.num_levels(), img2.num_levels());
         let mut num_levels_identical = 0;
         while num_levels_identical < img1.num_levels() {
@@ -92,6 +96,17 @@ impl KernelPyramidImage {
     }
 }
 
+impl Cacheable for KernelPyramidImage {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.original.write_to(output)?;
+        self.indexed_kernel_images.write_to(output)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(KernelPyramidImage {original: Image::read_from(input)?, indexed_kernel_images: Vec::read_from(input)?})
+    }
+}
+
 pub fn get_kernels_from(labeled_images: &Vec<(u8,Image)>, num_kernels: u8) -> Vec<Image> {
     let images_only = labeled_images.iter()
         .map(|(_,img)| img.clone())
@@ -100,9 +115,16 @@ pub fn get_kernels_from(labeled_images: &Vec<(u8,Image)>, num_kernels: u8) -> Ve
 }
 
 pub fn kernel_stack_all(labeled_images: &Vec<(u8,Image)>, kernels: &Vec<Image>, num_levels: usize) -> Vec<(u8, KernelPyramidImage)> {
+    kernel_stack_all_with(labeled_images, kernels, num_levels, &euclidean_distance)
+}
+
+// distance matches the original images' windows to the kernels. Higher levels hold kernel
+// indices rather than intensities, so they always match by Hamming distance.
+pub fn kernel_stack_all_with<V: Copy + PartialOrd, D: Fn(&Image,&Image) -> V>
+(labeled_images: &Vec<(u8,Image)>, kernels: &Vec<Image>, num_levels: usize, distance: &D) -> Vec<(u8, KernelPyramidImage)> {
     let mut pyramid_images = labeled_images.iter()
         .map(|(label, img)| (*label, KernelPyramidImage {original: img.clone(),
-            indexed_kernel_images: vec![indexed_kernel_image(&img, &kernels, &euclidean_distance)]}))
+            indexed_kernel_images: vec![indexed_kernel_image(&img, &kernels, distance)]}))
         .collect();
 
     for _level in 0..num_levels {
@@ -120,7 +142,8 @@ fn add_pyramid_level(pyramid_images: &mut Vec<(u8, KernelPyramidImage)>, num_ker
 }
 
 fn hamming_distance(img1: &Image, img2: &Image) -> u32 {
-    assert_eq!(img1.side(), img2.side());
+    assert_eq!(img1.width(), img2.width());
+    assert_eq!(img1.height(), img2.height());
     let mut distance = 0;
     for (x, y) in img1.x_y_iter() {
         if img1.get(x, y) != img2.get(x, y) {
@@ -134,7 +157,7 @@ fn index_mean(images: &Vec<&Image>) -> Image {
     assert!(!images.is_empty());
     assert!(images.iter().all(|img| img.len() == images[0].len()));
 
-    let mut result = Image::new();
+    let mut result = Image::with_width(images[0].width());
     for (x, y) in images[0].x_y_iter() {
         let pixels_from_each = images.iter().map(|img| img.get(x, y));
         let most_popular = mode_values(pixels_from_each).unwrap();
@@ -151,21 +174,70 @@ fn extract_indexed_kernels(pyramid_images: &Vec<(u8, KernelPyramidImage)>, num_k
     kmeans::Kmeans::new(num_kernels as usize, &candidates, hamming_distance, image_mean).move_means()
 }
 
-fn indexed_kernel_image<V: Copy + PartialEq + PartialOrd + Ord + Into<f64>, D: Fn(&Image,&Image) -> V>
+fn indexed_kernel_image<V: Copy + PartialOrd, D: Fn(&Image,&Image) -> V>
 (img: &Image, kernels: &Vec<Image>, distance: &D) -> Image {
-    let mut result = Image::new();
+    let mut result = Image::with_width(strided(img.width()));
     for (x, y) in img.x_y_step_iter(STRIDE) {
         result.add(classify_pixel(img, x, y, kernels, distance) as u8);
     }
     result
 }
 
-fn classify_pixel<V: Copy + PartialEq + PartialOrd + Ord, D: Fn(&Image,&Image) -> V>
+// Ties go to the lowest index. Only a partial order is needed, so that floating-point distances
+// such as SSIM dissimilarity also work.
+fn classify_pixel<V: Copy + PartialOrd, D: Fn(&Image,&Image) -> V>
 (img: &Image, x: usize, y: usize, kernels: &Vec<Image>, distance: &D) -> usize {
-    let (_, best_index) = kernels.iter()
+    let window = img.subimage(x, y, KERNEL_SIZE);
+    let (best_index, _) = kernels.iter()
+        .map(|kernel| distance(&window, kernel))
         .enumerate()
-        .map(|(i, kernel)| (distance(&img.subimage(x, y, KERNEL_SIZE), kernel), i))
-        .min()
+        .fold(None, |best: Option<(usize, V)>, (i, d)| match best {
+            Some((_, best_distance)) if d.partial_cmp(&best_distance) != Some(Ordering::Less) => best,
+            _ => Some((i, d))
+        })
         .unwrap();
     best_index
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssim::global_ssim_dissimilarity;
+    use crate::test_util::rectangular_image;
+
+    #[test]
+    fn test_level_shapes() {
+        let images = vec![(0, rectangular_image()), (1, rectangular_image())];
+        let kernels = get_kernels_from(&images, 4);
+        let pyramids = kernel_stack_all(&images, &kernels, 2);
+        let pyramid = &pyramids[0].1;
+        assert_eq!(3, pyramid.num_levels());
+        let shapes: Vec<(usize, usize)> = (0..3).map(|n| (pyramid.nth(n).width(), pyramid.nth(n).height())).collect();
+        assert_eq!(vec![(4, 3), (8, 5), (16, 10)], shapes);
+        let distance = KernelPyramidImage::distance(pyramid, &pyramids[1].1);
+        assert!(distance == KernelPyramidDistance {num_levels_identical: 3, distance_level_n: 0});
+    }
+
+    #[test]
+    fn test_ssim_kernels() {
+        let img = Image::test_pattern(8, 8);
+        let kernels = vec![Image::from_slice(&[0; 9], 3, 3), img.subimage(4, 4, KERNEL_SIZE), img.subimage(4, 4, KERNEL_SIZE)];
+        assert_eq!(1, classify_pixel(&img, 4, 4, &kernels, &global_ssim_dissimilarity));
+        assert_eq!(1, classify_pixel(&img, 4, 4, &kernels, &euclidean_distance));
+        let images = vec![(0, img)];
+        let pyramid = &kernel_stack_all_with(&images, &kernels, 1, &global_ssim_dissimilarity)[0].1;
+        assert_eq!(2, pyramid.num_levels());
+        assert_eq!((4, 4), (pyramid.nth(1).width(), pyramid.nth(1).height()));
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let images = vec![(0, Image::test_pattern(16, 12))];
+        let pyramid = &kernel_stack_all(&images, &get_kernels_from(&images, 4), 1)[0].1;
+        let mut bytes = Vec::new();
+        pyramid.write_to(&mut bytes).unwrap();
+        let restored = KernelPyramidImage::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(pyramid.original, restored.original);
+        assert_eq!(pyramid.indexed_kernel_images, restored.indexed_kernel_images);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::mnist_data::{Image, image_mean, Grid};
+use crate::mnist_data::{Image, image_mean, Grid, PixelImage};
 use kmeans;
-use crate::euclidean_distance::euclidean_distance;
+use crate::euclidean_distance::{euclidean_distance, euclidean_distance_f64};
+use crate::pixel::Pixel;
 
 const NUM_KERNELS: usize = 8;
 const KERNEL_SIZE: usize = 3;
@@ -25,6 +26,28 @@ pub fn kernelized_distance(k1: &Vec<Image>, k2: &Vec<Image>) -> u32 {
     (0..k1.len()).map(|i| euclidean_distance(&k1[i], &k2[i])).sum()
 }
 
+// Same as kernelize_all(), but kernel distances are not squashed into u8 pixels.
+pub fn kernelize_all_f32(labeled_images: &Vec<(u8,Image)>, levels: usize) -> Vec<(u8,Vec<PixelImage<f32>>)> {
+    let images_only = labeled_images.iter().map(|(_,img)| img.clone()).collect();
+    let kernels = extract_kernels_from(&images_only, NUM_KERNELS, KERNEL_SIZE);
+    let mut kernelized: Vec<(u8,Vec<PixelImage<f32>>)> = labeled_images.iter()
+        .map(|(label, img)| (*label, vec![img.to_f32()]))
+        .collect();
+    for _ in 0..levels {
+        kernelized = kernelized.iter()
+            .map(|(label, images)| (*label, images.iter()
+                .flat_map(|img| kernels.iter().map(|kernel| apply_kernel_f32(img, kernel)))
+                .collect()))
+            .collect();
+    }
+    kernelized
+}
+
+pub fn kernelized_distance_f32(k1: &Vec<PixelImage<f32>>, k2: &Vec<PixelImage<f32>>) -> f64 {
+    assert_eq!(k1.len(), k2.len());
+    (0..k1.len()).map(|i| euclidean_distance_f64(&k1[i], &k2[i])).sum()
+}
+
 pub fn extract_kernels_from(images: &Vec<Image>, num_kernels: usize, kernel_size: usize) -> Vec<Image> {
     let mut candidates = Vec::new();
     for img in images.iter() {
@@ -48,19 +71,41 @@ pub fn project_image_through(img: &Image, kernels: &Vec<Image>) -> Vec<Image> {
 
 pub fn apply_kernel_to(img: &Image, kernel: &Image) -> Image {
     assert_eq!(kernel.side(), KERNEL_SIZE);
-    let mut result = Image::new();
+    let mut result = Image::with_width(strided(img.width()));
     for (x, y) in img.x_y_step_iter(STRIDE) {
         result.add(pixelize(euclidean_distance(&img.subimage(x, y, KERNEL_SIZE), kernel)));
     }
     result
 }
 
+pub fn apply_kernel_f32<P: Pixel + Into<f32>>(img: &PixelImage<P>, kernel: &Image) -> PixelImage<f32> {
+    assert_eq!(kernel.side(), KERNEL_SIZE);
+    let kernel = kernel.to_f32();
+    let mut result = PixelImage::with_width(strided(img.width()));
+    for (x, y) in img.x_y_step_iter(STRIDE) {
+        let window = img.subimage(x, y, KERNEL_SIZE).map(|p| p.into());
+        result.add(pixelize_f32(euclidean_distance_f64(&window, &kernel)));
+    }
+    result
+}
+
+// Number of positions along a dimension of the given length visited with a stride of STRIDE.
+pub fn strided(length: usize) -> usize {
+    length.div_ceil(STRIDE)
+}
+
 pub fn pixelize(distance: u32) -> u8 {
     let max_distance = ((u8::MAX as f64).powf(2.0) * (KERNEL_SIZE.pow(2) as f64)).powf(0.5);
     let distance_to_pixel_scale = (u8::MAX as f64) / max_distance;
     ((distance as f64).powf(0.5) * distance_to_pixel_scale) as u8
 }
 
+pub fn pixelize_f32(distance: f64) -> f32 {
+    let max_distance = ((u8::MAX as f64).powf(2.0) * (KERNEL_SIZE.pow(2) as f64)).powf(0.5);
+    let distance_to_pixel_scale = (u8::MAX as f64) / max_distance;
+    (distance.powf(0.5) * distance_to_pixel_scale) as f32
+}
+
 pub fn add_kernels_from_to(img: &Image, raw_filters: &mut Vec<Image>, kernel_size: usize) {
     img.x_y_iter().
         for_each(|(x, y)| raw_filters.push(img.subimage(x, y, kernel_size)));
@@ -69,6 +114,7 @@ pub fn add_kernels_from_to(img: &Image, raw_filters: &mut Vec<Image>, kernel_siz
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::rectangular_image;
 
     #[test]
     fn test_kernels() {
@@ -82,6 +128,40 @@ mod tests {
                 test_filter_means(&target_means_2, &filter_means));
     }
 
+    #[test]
+    fn test_projection_shapes() {
+        let img = rectangular_image();
+        let kernel = img.subimage(4, 4, KERNEL_SIZE);
+        let projected = apply_kernel_to(&img, &kernel);
+        assert_eq!((16, 10), (projected.width(), projected.height()));
+        assert_eq!(0, projected.get(2, 2));
+
+        let odd = apply_kernel_to(&Image::test_pattern(31, 19), &kernel);
+        assert_eq!((16, 10), (odd.width(), odd.height()));
+
+        let kernelized = kernelize_all(&vec![(0, img.clone()), (1, img.clone())], 1);
+        assert_eq!(NUM_KERNELS, kernelized[0].1.len());
+        assert!(kernelized[0].1.iter().all(|k| (k.width(), k.height()) == (16, 10)));
+        assert_eq!(0, kernelized_distance(&kernelized[0].1, &kernelized[1].1));
+    }
+
+    #[test]
+    fn test_f32_matches_u8() {
+        let img = rectangular_image();
+        let kernel = img.subimage(7, 3, KERNEL_SIZE);
+        let projected = apply_kernel_to(&img, &kernel);
+        let precise = apply_kernel_f32(&img, &kernel);
+        assert_eq!((16, 10), (precise.width(), precise.height()));
+        for (x, y) in precise.x_y_iter() {
+            assert_eq!(projected.get(x, y), precise.get(x, y) as u8);
+        }
+        assert_eq!(projected, precise.map(|p| p as u8));
+
+        let kernelized = kernelize_all_f32(&vec![(0, img.clone()), (1, img.clone())], 2);
+        assert_eq!(NUM_KERNELS * NUM_KERNELS, kernelized[0].1.len());
+        assert_eq!(0.0, kernelized_distance_f32(&kernelized[0].1, &kernelized[1].1));
+    }
+
     fn test_filter_means(target_means: &Vec<u8>, filter_means: &Vec<u8>) -> bool {
         for mean in filter_means.iter() {
             if !target_means.contains(mean) && !target_means.contains(&(mean - 1)) && !target_means.contains(&(mean + 1)) {
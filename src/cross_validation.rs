@@ -0,0 +1,227 @@
+//! Cross-validation of kNN distance variants on a single labeled set, so that parameters such as k,
+//! neighbor counts, and kernel counts can be tuned without looking at the testing set.
+//!
+//! The inputs mirror `ExperimentData::build_and_test_model` (a per-image conversion) and
+//! `build_and_test_converting_all` (a whole-set conversion). A whole-set conversion may mine kernels
+//! from its input, so it runs separately on each training and testing fold, keeping held-out images
+//! out of the mining.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use supervised_learning::Classifier;
+use crate::mnist_data::Image;
+use crate::sampling::{random_sample, stratified_sample, proportional_quotas};
+use crate::statistics::{mean, standard_deviation};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scheme {
+    KFold(usize),
+    RepeatedHoldout {repetitions: usize, testing_fraction: f64}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CrossValidation {
+    pub scheme: Scheme,
+    pub stratified: bool,
+    pub seed: u64
+}
+
+// Indices of the training and testing items of one fold.
+pub type Split = (Vec<usize>, Vec<usize>);
+
+impl CrossValidation {
+    pub fn k_fold(folds: usize) -> Self {
+        assert!(folds >= 2, "k-fold cross-validation needs at least 2 folds");
+        CrossValidation {scheme: Scheme::KFold(folds), stratified: false, seed: 0}
+    }
+
+    pub fn repeated_holdout(repetitions: usize, testing_fraction: f64) -> Self {
+        assert!(repetitions >= 1);
+        assert!(testing_fraction > 0.0 && testing_fraction < 1.0, "testing fraction must be strictly between 0 and 1");
+        CrossValidation {scheme: Scheme::RepeatedHoldout {repetitions, testing_fraction}, stratified: false, seed: 0}
+    }
+
+    pub fn stratified(mut self) -> Self {
+        self.stratified = true;
+        self
+    }
+
+    pub fn seeded(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn splits(&self, labels: &[u8]) -> Vec<Split> {
+        let indexed: Vec<(u8,usize)> = labels.iter().enumerate().map(|(i, label)| (*label, i)).collect();
+        match self.scheme {
+            Scheme::KFold(folds) => self.k_fold_splits(&indexed, folds),
+            Scheme::RepeatedHoldout {repetitions, testing_fraction} => {
+                let testing_size = ((labels.len() as f64 * testing_fraction).round() as usize).max(1);
+                (0..repetitions as u64)
+                    .map(|r| {
+                        let seed = self.seed.wrapping_add(r);
+                        let testing = if self.stratified {
+                            stratified_sample(&indexed, &proportional_quotas(&indexed, testing_size), seed)
+                        } else {
+                            random_sample(&indexed, testing_size, seed)
+                        };
+                        complement(labels.len(), testing.iter().map(|(_, i)| *i).collect())
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Stratified folds deal each class out in turn, so every fold gets its share of every class.
+    fn k_fold_splits(&self, indexed: &[(u8,usize)], folds: usize) -> Vec<Split> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut order: Vec<(u8,usize)> = indexed.to_vec();
+        order.shuffle(&mut rng);
+        if self.stratified {
+            order.sort_by_key(|(label, _)| *label);
+        }
+        let mut fold_members: Vec<Vec<usize>> = vec![Vec::new(); folds];
+        for (position, (_, i)) in order.iter().enumerate() {
+            fold_members[position % folds].push(*i);
+        }
+        fold_members.into_iter().map(|mut testing| {
+            testing.sort_unstable();
+            complement(indexed.len(), testing)
+        }).collect()
+    }
+
+    // The conversion runs once over all items, since each image converts on its own.
+    pub fn evaluate_model<I: Clone, M: Copy + PartialEq + PartialOrd, C: Fn(&Image) -> I, D: Fn(&I,&I) -> M>
+    (&self, data: &[(u8,Image)], k: usize, conversion: C, distance: D) -> CrossValidationResult {
+        let converted: Vec<(u8,I)> = data.iter().map(|(label, img)| (*label, conversion(img))).collect();
+        let labels: Vec<u8> = data.iter().map(|(label, _)| *label).collect();
+        let error_percentages = self.splits(&labels).iter()
+            .map(|(training, testing)| error_percentage(&select(&converted, training), &select(&converted, testing), k, &distance))
+            .collect();
+        CrossValidationResult {validation: *self, error_percentages}
+    }
+
+    pub fn evaluate_converting_all<I: Clone, M: Copy + PartialEq + PartialOrd, C: Fn(&Vec<(u8,Image)>) -> Vec<(u8,I)>, D: Fn(&I,&I) -> M>
+    (&self, data: &[(u8,Image)], k: usize, conversion: C, distance: D) -> CrossValidationResult {
+        let labels: Vec<u8> = data.iter().map(|(label, _)| *label).collect();
+        let error_percentages = self.splits(&labels).iter()
+            .map(|(training, testing)| {
+                let training = conversion(&select(data, training));
+                let testing = conversion(&select(data, testing));
+                error_percentage(&training, &testing, k, &distance)
+            })
+            .collect();
+        CrossValidationResult {validation: *self, error_percentages}
+    }
+}
+
+impl Display for CrossValidation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.scheme {
+            Scheme::KFold(folds) => write!(f, "{}-fold", folds)?,
+            Scheme::RepeatedHoldout {repetitions, testing_fraction} =>
+                write!(f, "{} x holdout of {:.0}%", repetitions, testing_fraction * 100.0)?
+        }
+        if self.stratified {
+            write!(f, ", stratified")?;
+        }
+        write!(f, ", seed {}", self.seed)
+    }
+}
+
+fn complement(len: usize, testing: Vec<usize>) -> Split {
+    let mut held_out = vec![false; len];
+    testing.iter().for_each(|i| held_out[*i] = true);
+    ((0..len).filter(|i| !held_out[*i]).collect(), testing)
+}
+
+fn select<T: Clone>(items: &[(u8,T)], indices: &[usize]) -> Vec<(u8,T)> {
+    indices.iter().map(|i| items[*i].clone()).collect()
+}
+
+fn error_percentage<I: Clone, M: Copy + PartialEq + PartialOrd, D: Fn(&I,&I) -> M>
+(training: &Vec<(u8,I)>, testing: &Vec<(u8,I)>, k: usize, distance: D) -> f64 {
+    let mut model = knn::Knn::new(k, distance);
+    model.train(training);
+    model.test(testing).error_rate() * 100.0
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossValidationResult {
+    pub validation: CrossValidation,
+    pub error_percentages: Vec<f64>
+}
+
+impl CrossValidationResult {
+    pub fn mean(&self) -> f64 {
+        mean(&self.error_percentages)
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        standard_deviation(&self.error_percentages)
+    }
+}
+
+impl Display for CrossValidationResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Error rate: {:.2}% ± {:.2}% over {} folds ({})", self.mean(), self.standard_deviation(),
+               self.error_percentages.len(), self.validation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::euclidean_distance::euclidean_distance;
+    use crate::sampling::class_counts;
+
+    // Two classes of flat images, dark and light, so any sensible fold classifies perfectly.
+    fn two_classes() -> Vec<(u8,Image)> {
+        (0..30).map(|i| {
+            let label = if i % 3 == 0 {1} else {0};
+            (label, Image::from_slice(&[label * 200 + (i % 5) as u8; 16], 4, 4))
+        }).collect()
+    }
+
+    #[test]
+    fn test_k_fold_splits() {
+        let labels: Vec<u8> = two_classes().iter().map(|(label, _)| *label).collect();
+        let splits = CrossValidation::k_fold(5).stratified().seeded(3).splits(&labels);
+        assert_eq!(5, splits.len());
+        let mut tested: Vec<usize> = splits.iter().flat_map(|(_, testing)| testing.clone()).collect();
+        tested.sort_unstable();
+        assert_eq!((0..30).collect::<Vec<_>>(), tested);
+        for (training, testing) in splits.iter() {
+            assert_eq!(30, training.len() + testing.len());
+            assert!(training.iter().all(|i| !testing.contains(i)));
+            let fold: Vec<(u8,usize)> = testing.iter().map(|i| (labels[*i], *i)).collect();
+            assert_eq!(vec![4, 2], class_counts(&fold).values().cloned().collect::<Vec<_>>());
+        }
+        assert_eq!(splits, CrossValidation::k_fold(5).stratified().seeded(3).splits(&labels));
+    }
+
+    #[test]
+    fn test_holdout_splits() {
+        let labels: Vec<u8> = two_classes().iter().map(|(label, _)| *label).collect();
+        let splits = CrossValidation::repeated_holdout(4, 0.3).stratified().splits(&labels);
+        assert_eq!(4, splits.len());
+        assert!(splits.iter().all(|(training, testing)| training.len() == 21 && testing.len() == 9));
+        assert_ne!(splits[0], splits[1]);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let data = two_classes();
+        let result = CrossValidation::k_fold(3).stratified().evaluate_model(&data, 3, |img| img.clone(), euclidean_distance);
+        assert_eq!(vec![0.0; 3], result.error_percentages);
+        assert_eq!(0.0, result.standard_deviation());
+        let converted = CrossValidation::repeated_holdout(2, 0.2)
+            .evaluate_converting_all(&data, 1, |images| images.clone(), euclidean_distance);
+        assert_eq!(2, converted.error_percentages.len());
+        assert_eq!(0.0, converted.mean());
+        assert!(converted.to_string().contains("2 x holdout of 20%"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Finds the directory holding the IDX data files. Candidate directories are checked in order:
+//!
+//! 1. The `data_dir=<path>` command-line option.
+//! 2. The `DISTANCE_RESEARCH_DATA` environment variable.
+//! 3. A `data_dir = <path>` line in the `distance_research.cfg` project config file, which lives in
+//!    the project root, so it is found whatever directory the program runs from.
+//!
+//! The first candidate containing every required file, raw or gzip-compressed, wins. If none does,
+//! the error lists every path that was tried.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs, io};
+use crate::idx::existing_path;
+
+pub const DATA_DIR_OPTION: &str = "data_dir=";
+pub const DATA_DIR_ENV_VAR: &str = "DISTANCE_RESEARCH_DATA";
+pub const CONFIG_FILE: &str = "distance_research.cfg";
+pub const CONFIG_KEY: &str = "data_dir";
+
+pub fn config_file_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(CONFIG_FILE)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    source: String,
+    dir: PathBuf
+}
+
+impl Candidate {
+    pub fn new(source: &str, dir: PathBuf) -> Self {
+        Candidate {source: source.to_string(), dir}
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.as_path()
+    }
+
+    fn missing_files(&self, required_files: &[String]) -> Vec<String> {
+        required_files.iter()
+            .filter(|f| existing_path(&self.dir, f).is_none())
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DataLocationError {
+    tried: Vec<(Candidate, Vec<String>)>
+}
+
+impl DataLocationError {
+    pub fn tried(&self) -> Vec<&Path> {
+        self.tried.iter().map(|(c, _)| c.dir()).collect()
+    }
+}
+
+impl fmt::Display for DataLocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tried.is_empty() {
+            return write!(f, "No data directory configured. Use the {}<path> option, set {}, or add a \"{} = <path>\" line to {}.",
+                          DATA_DIR_OPTION, DATA_DIR_ENV_VAR, CONFIG_KEY, config_file_path().display());
+        }
+        writeln!(f, "Could not find the data files. Tried:")?;
+        for (candidate, missing) in self.tried.iter() {
+            writeln!(f, "\t{} ({}): missing {}", candidate.dir.display(), candidate.source, missing.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DataLocationError {}
+
+impl From<DataLocationError> for io::Error {
+    fn from(e: DataLocationError) -> Self {
+        io::Error::new(io::ErrorKind::NotFound, e)
+    }
+}
+
+pub fn resolve_data_dir(args: &HashSet<String>, required_files: &[String]) -> Result<PathBuf, DataLocationError> {
+    resolve_from(&candidates(args), required_files)
+}
+
+pub fn candidates(args: &HashSet<String>) -> Vec<Candidate> {
+    let mut result = Vec::new();
+    if let Some(dir) = args.iter().find_map(|arg| arg.strip_prefix(DATA_DIR_OPTION)) {
+        result.push(Candidate::new(&format!("{} option", DATA_DIR_OPTION), PathBuf::from(dir)));
+    }
+    if let Ok(dir) = env::var(DATA_DIR_ENV_VAR) {
+        result.push(Candidate::new(&format!("{} variable", DATA_DIR_ENV_VAR), PathBuf::from(dir)));
+    }
+    if let Some(dir) = config_file_entry(&config_file_path()) {
+        result.push(Candidate::new(CONFIG_FILE, dir));
+    }
+    result
+}
+
+pub fn resolve_from(candidates: &[Candidate], required_files: &[String]) -> Result<PathBuf, DataLocationError> {
+    let mut tried = Vec::new();
+    for candidate in candidates.iter() {
+        let missing = candidate.missing_files(required_files);
+        if missing.is_empty() {
+            return Ok(candidate.dir.clone());
+        }
+        tried.push((candidate.clone(), missing));
+    }
+    Err(DataLocationError {tried})
+}
+
+// Relative paths in the config file are taken relative to the directory containing it.
+fn config_file_entry(config_file: &Path) -> Option<PathBuf> {
+    let contents = fs::read_to_string(config_file).ok()?;
+    let value = parse_config(&contents, CONFIG_KEY)?;
+    Some(config_file.parent().unwrap_or(Path::new("")).join(value))
+}
+
+fn parse_config(contents: &str, key: &str) -> Option<String> {
+    contents.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .find(|(k, _)| k.trim() == key)
+        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn scratch_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = temp_path(name);
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_first_complete_candidate_wins() {
+        let required = vec!["a".to_string(), "b".to_string()];
+        let partial = scratch_dir("partial", &["a"]);
+        let complete = scratch_dir("complete", &["a", "b.gz"]);
+        let found = resolve_from(&[Candidate::new("first", partial.clone()), Candidate::new("second", complete.clone())], &required).unwrap();
+        assert_eq!(complete, found);
+        fs::remove_dir_all(partial).unwrap();
+        fs::remove_dir_all(complete).unwrap();
+    }
+
+    #[test]
+    fn test_error_lists_all_tried() {
+        let required = vec!["a".to_string()];
+        let candidates = vec![Candidate::new("x", PathBuf::from("/nonexistent/x")), Candidate::new("y", PathBuf::from("/nonexistent/y"))];
+        let err = resolve_from(&candidates, &required).unwrap_err();
+        assert_eq!(vec![Path::new("/nonexistent/x"), Path::new("/nonexistent/y")], err.tried());
+        let message = err.to_string();
+        assert!(message.contains("/nonexistent/x") && message.contains("/nonexistent/y"));
+    }
+
+    #[test]
+    fn test_cli_option_comes_first() {
+        let args: HashSet<String> = ["baseline", "data_dir=/from/cli"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(Path::new("/from/cli"), candidates(&args)[0].dir());
+    }
+
+    #[test]
+    fn test_config_file_is_in_project_root() {
+        let path = config_file_path();
+        assert!(path.is_absolute());
+        assert!(path.with_file_name("Cargo.toml").is_file());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let contents = "# comment\nother = 1\ndata_dir = \"/data/mnist\"\n";
+        assert_eq!(Some("/data/mnist".to_string()), parse_config(contents, CONFIG_KEY));
+        assert_eq!(None, parse_config("", CONFIG_KEY));
+    }
+}
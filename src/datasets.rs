@@ -0,0 +1,190 @@
+//! Registry of the MNIST-format datasets we compare distance functions on.
+//!
+//! Each dataset knows its IDX file names, class count, and class names. MNIST files sit directly
+//! in the data directory; every other dataset lives in its own subdirectory of it.
+//!
+//! EMNIST stores its images transposed relative to MNIST, so they are transposed while loading.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::idx::LoadMode;
+use crate::mnist_data::{Image, image_file_name, label_file_name, load_data_set_with};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmnistSplit {
+    ByClass, ByMerge, Balanced, Letters, Digits, Mnist
+}
+
+impl EmnistSplit {
+    pub fn all() -> Vec<EmnistSplit> {
+        vec![EmnistSplit::ByClass, EmnistSplit::ByMerge, EmnistSplit::Balanced, EmnistSplit::Letters, EmnistSplit::Digits, EmnistSplit::Mnist]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmnistSplit::ByClass => "byclass",
+            EmnistSplit::ByMerge => "bymerge",
+            EmnistSplit::Balanced => "balanced",
+            EmnistSplit::Letters => "letters",
+            EmnistSplit::Digits => "digits",
+            EmnistSplit::Mnist => "mnist"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dataset {
+    Mnist, FashionMnist, Kmnist, Emnist(EmnistSplit)
+}
+
+const FASHION_CLASSES: [&str; 10] = ["T-shirt/top", "Trouser", "Pullover", "Dress", "Coat", "Sandal", "Shirt", "Sneaker", "Bag", "Ankle boot"];
+const KMNIST_CLASSES: [&str; 10] = ["o", "ki", "su", "tsu", "na", "ha", "ma", "ya", "re", "wo"];
+// Lowercase letters that EMNIST ByMerge and Balanced keep distinct from their uppercase forms.
+const UNMERGED_LOWERCASE: &str = "abdefghnqrt";
+
+impl Dataset {
+    pub fn all() -> Vec<Dataset> {
+        let mut result = vec![Dataset::Mnist, Dataset::FashionMnist, Dataset::Kmnist];
+        result.extend(EmnistSplit::all().iter().map(|split| Dataset::Emnist(*split)));
+        result
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Dataset::Mnist => "mnist".to_string(),
+            Dataset::FashionMnist => "fashion".to_string(),
+            Dataset::Kmnist => "kmnist".to_string(),
+            Dataset::Emnist(split) => format!("emnist_{}", split.name())
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Dataset> {
+        Dataset::all().into_iter().find(|d| d.name() == name)
+    }
+
+    pub fn subdirectory(&self) -> &'static str {
+        match self {
+            Dataset::Mnist => "",
+            Dataset::FashionMnist => "fashion_mnist",
+            Dataset::Kmnist => "kmnist",
+            Dataset::Emnist(_) => "emnist"
+        }
+    }
+
+    pub fn training_prefix(&self) -> String {
+        match self {
+            Dataset::Emnist(split) => format!("emnist-{}-train", split.name()),
+            _ => "train".to_string()
+        }
+    }
+
+    pub fn testing_prefix(&self) -> String {
+        match self {
+            Dataset::Emnist(split) => format!("emnist-{}-test", split.name()),
+            _ => "t10k".to_string()
+        }
+    }
+
+    pub fn required_files(&self) -> Vec<String> {
+        [self.training_prefix(), self.testing_prefix()].iter()
+            .flat_map(|prefix| vec![image_file_name(prefix), label_file_name(prefix)])
+            .map(|file| Path::new(self.subdirectory()).join(file).to_string_lossy().into_owned())
+            .collect()
+    }
+
+    pub fn is_transposed(&self) -> bool {
+        matches!(self, Dataset::Emnist(_))
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.class_names().len()
+    }
+
+    // EMNIST Letters labels run from 1 to 26; every other dataset starts at 0.
+    pub fn first_label(&self) -> u8 {
+        if *self == Dataset::Emnist(EmnistSplit::Letters) {1} else {0}
+    }
+
+    pub fn class_names(&self) -> Vec<String> {
+        let digits = ('0'..='9').map(|c| c.to_string());
+        let uppercase = ('A'..='Z').map(|c| c.to_string());
+        match self {
+            Dataset::Mnist | Dataset::Emnist(EmnistSplit::Digits) | Dataset::Emnist(EmnistSplit::Mnist) => digits.collect(),
+            Dataset::FashionMnist => FASHION_CLASSES.iter().map(|s| s.to_string()).collect(),
+            Dataset::Kmnist => KMNIST_CLASSES.iter().map(|s| s.to_string()).collect(),
+            Dataset::Emnist(EmnistSplit::Letters) => uppercase.collect(),
+            Dataset::Emnist(EmnistSplit::ByClass) => digits.chain(uppercase).chain(('a'..='z').map(|c| c.to_string())).collect(),
+            Dataset::Emnist(EmnistSplit::ByMerge) | Dataset::Emnist(EmnistSplit::Balanced) =>
+                digits.chain(uppercase).chain(UNMERGED_LOWERCASE.chars().map(|c| c.to_string())).collect()
+        }
+    }
+
+    pub fn class_name(&self, label: u8) -> Option<String> {
+        let index = label.checked_sub(self.first_label())? as usize;
+        self.class_names().get(index).cloned()
+    }
+
+    pub fn directory(&self, data_dir: &Path) -> PathBuf {
+        data_dir.join(self.subdirectory())
+    }
+
+    pub fn load_training(&self, data_dir: &Path, mode: LoadMode) -> io::Result<Vec<(u8,Image)>> {
+        self.load(data_dir, &self.training_prefix(), mode)
+    }
+
+    pub fn load_testing(&self, data_dir: &Path, mode: LoadMode) -> io::Result<Vec<(u8,Image)>> {
+        self.load(data_dir, &self.testing_prefix(), mode)
+    }
+
+    fn load(&self, data_dir: &Path, file_prefix: &str, mode: LoadMode) -> io::Result<Vec<(u8,Image)>> {
+        let images = load_data_set_with(&self.directory(data_dir), file_prefix, mode)?;
+        if self.is_transposed() {
+            Ok(images.iter().map(|(label, img)| (*label, img.transposed())).collect())
+        } else {
+            Ok(images)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        for dataset in Dataset::all() {
+            assert_eq!(Some(dataset), Dataset::from_name(&dataset.name()));
+        }
+        assert_eq!(None, Dataset::from_name("cifar"));
+    }
+
+    #[test]
+    fn test_class_counts() {
+        assert_eq!(10, Dataset::Mnist.num_classes());
+        assert_eq!(10, Dataset::FashionMnist.num_classes());
+        assert_eq!(10, Dataset::Kmnist.num_classes());
+        assert_eq!(62, Dataset::Emnist(EmnistSplit::ByClass).num_classes());
+        assert_eq!(47, Dataset::Emnist(EmnistSplit::ByMerge).num_classes());
+        assert_eq!(47, Dataset::Emnist(EmnistSplit::Balanced).num_classes());
+        assert_eq!(26, Dataset::Emnist(EmnistSplit::Letters).num_classes());
+        assert_eq!(10, Dataset::Emnist(EmnistSplit::Digits).num_classes());
+    }
+
+    #[test]
+    fn test_class_names() {
+        assert_eq!(Some("Ankle boot".to_string()), Dataset::FashionMnist.class_name(9));
+        assert_eq!(Some("A".to_string()), Dataset::Emnist(EmnistSplit::Letters).class_name(1));
+        assert_eq!(None, Dataset::Emnist(EmnistSplit::Letters).class_name(0));
+        assert_eq!(Some("t".to_string()), Dataset::Emnist(EmnistSplit::Balanced).class_name(46));
+        assert_eq!(Some("z".to_string()), Dataset::Emnist(EmnistSplit::ByClass).class_name(61));
+    }
+
+    #[test]
+    fn test_files() {
+        assert_eq!(vec!["train-images-idx3-ubyte", "train-labels-idx1-ubyte", "t10k-images-idx3-ubyte", "t10k-labels-idx1-ubyte"],
+                   Dataset::Mnist.required_files());
+        assert!(Dataset::Emnist(EmnistSplit::Letters).required_files().contains(&"emnist/emnist-letters-test-labels-idx1-ubyte".to_string()));
+        assert!(Dataset::Emnist(EmnistSplit::Digits).is_transposed());
+        assert!(!Dataset::Kmnist.is_transposed());
+    }
+}
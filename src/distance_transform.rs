@@ -0,0 +1,196 @@
+//! Euclidean distance transforms and the shape distances built on them.
+//!
+//! The transform is Felzenszwalb and Huttenlocher's (2012) lower envelope of parabolas, applied to
+//! the columns and then the rows, so it is linear in the number of pixels. With each shape's
+//! transform precomputed, chamfer and Hausdorff distances cost one lookup per point instead of a
+//! search over the other shape's points, as `kernel_points::closest_for_all` does.
+
+use std::io;
+use std::io::{Read, Write};
+use crate::feature_cache::Cacheable;
+use crate::mnist_data::{Image, Grid, PixelImage};
+use crate::morphology::{binarized, otsu_threshold};
+use crate::sobel::edge_image;
+
+// Stands in for infinity, which would turn the parabola intersections into NaN.
+const FAR: f64 = 1e20;
+
+// Squared distance from each index q to the nearest i, where reaching i costs f[i] extra.
+fn squared_distances_1d(f: &[f64]) -> Vec<f64> {
+    if f.is_empty() {
+        return Vec::new();
+    }
+    let n = f.len();
+    let mut vertices = vec![0; n];
+    let mut boundaries = vec![0.0; n + 1];
+    let mut k = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+    let intersection = |q: usize, v: usize| ((f[q] + (q * q) as f64) - (f[v] + (v * v) as f64)) / (2 * q - 2 * v) as f64;
+    for q in 1..n {
+        let mut s = intersection(q, vertices[k]);
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(q, vertices[k]);
+        }
+        k += 1;
+        vertices[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f64::INFINITY;
+    }
+    k = 0;
+    (0..n).map(|q| {
+        while boundaries[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - vertices[k] as f64;
+        offset * offset + f[vertices[k]]
+    }).collect()
+}
+
+// Squared Euclidean distance from each pixel to the nearest pixel brighter than threshold, in
+// row-major order. Without any such pixel, every value is at least 1e20.
+pub fn squared_distance_transform(img: &Image, threshold: u8) -> Vec<f64> {
+    let (width, height) = (img.width(), img.height());
+    let mut values: Vec<f64> = img.pixels().iter().map(|p| if *p > threshold {0.0} else {FAR}).collect();
+    for x in 0..width {
+        let column: Vec<f64> = (0..height).map(|y| values[y * width + x]).collect();
+        for (y, value) in squared_distances_1d(&column).into_iter().enumerate() {
+            values[y * width + x] = value;
+        }
+    }
+    for y in 0..height {
+        let row = squared_distances_1d(&values[y * width..(y + 1) * width]);
+        values[y * width..(y + 1) * width].copy_from_slice(&row);
+    }
+    values
+}
+
+// A set of foreground points, with the distance from every pixel to the nearest of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shape {
+    points: Vec<(usize,usize)>,
+    distances: PixelImage<f32>
+}
+
+impl Shape {
+    // An empty shape is as far from every pixel as the image's diagonal.
+    pub fn new(img: &Image, threshold: u8) -> Self {
+        let points = img.x_y_iter().filter(|(x, y)| img.get(*x, *y) > threshold).collect();
+        let diagonal = ((img.width() * img.width() + img.height() * img.height()) as f64).sqrt();
+        let distances: Vec<f32> = squared_distance_transform(img, threshold).iter().map(|d| d.sqrt().min(diagonal) as f32).collect();
+        Shape {points, distances: PixelImage::from_slice(&distances, img.width(), img.height())}
+    }
+
+    // Foreground by Otsu's threshold.
+    pub fn thresholded(img: &Image) -> Self {
+        Shape::new(&binarized(img, otsu_threshold(img)), 0)
+    }
+
+    // Otsu-thresholded Sobel edges.
+    pub fn edges(img: &Image) -> Self {
+        Shape::thresholded(&edge_image(img))
+    }
+
+    pub fn points(&self) -> &Vec<(usize,usize)> {
+        &self.points
+    }
+
+    pub fn distance_at(&self, x: usize, y: usize) -> f64 {
+        self.distances.get(x, y) as f64
+    }
+
+    // Distances from each of this shape's points to the nearest point of other.
+    fn distances_to<'a>(&'a self, other: &'a Shape) -> impl Iterator<Item=f64> + 'a {
+        assert_eq!((self.distances.width(), self.distances.height()), (other.distances.width(), other.distances.height()));
+        self.points.iter().map(move |(x, y)| other.distance_at(*x, *y))
+    }
+
+    // Mean distance from this shape's points to other; 0 if this shape is empty.
+    pub fn directed_chamfer(&self, other: &Shape) -> f64 {
+        if self.points.is_empty() {0.0} else {self.distances_to(other).sum::<f64>() / self.points.len() as f64}
+    }
+
+    // Farthest any of this shape's points lies from other.
+    pub fn directed_hausdorff(&self, other: &Shape) -> f64 {
+        self.distances_to(other).fold(0.0, f64::max)
+    }
+}
+
+impl Cacheable for Shape {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.points.write_to(output)?;
+        self.distances.write_to(output)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(Shape {points: Vec::read_from(input)?, distances: PixelImage::read_from(input)?})
+    }
+}
+
+pub fn chamfer_distance(s1: &Shape, s2: &Shape) -> f64 {
+    s1.directed_chamfer(s2) + s2.directed_chamfer(s1)
+}
+
+pub fn directed_hausdorff_distance(s1: &Shape, s2: &Shape) -> f64 {
+    s1.directed_hausdorff(s2)
+}
+
+pub fn hausdorff_distance(s1: &Shape, s2: &Shape) -> f64 {
+    s1.directed_hausdorff(s2).max(s2.directed_hausdorff(s1))
+}
+
+// Dubuisson and Jain (1994): the larger of the two mean directed distances, which is far less
+// sensitive to stray points than the Hausdorff distance.
+pub fn modified_hausdorff_distance(s1: &Shape, s2: &Shape) -> f64 {
+    s1.directed_chamfer(s2).max(s2.directed_chamfer(s1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points_image(width: usize, height: usize, points: &[(usize,usize)]) -> Image {
+        let mut pixels = vec![0; width * height];
+        points.iter().for_each(|(x, y)| pixels[y * width + x] = 255);
+        Image::from_slice(&pixels, width, height)
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let points = [(1, 1), (6, 2), (3, 7), (8, 8)];
+        let img = points_image(10, 9, &points);
+        let transform = squared_distance_transform(&img, 0);
+        for (x, y) in img.x_y_iter() {
+            let nearest = points.iter()
+                .map(|(px, py)| (x.abs_diff(*px).pow(2) + y.abs_diff(*py).pow(2)) as f64)
+                .fold(f64::MAX, f64::min);
+            assert_eq!(nearest, transform[y * 10 + x]);
+        }
+        assert!(squared_distances_1d(&[]).is_empty());
+        assert_eq!(vec![0.0], squared_distances_1d(&[0.0]));
+    }
+
+    #[test]
+    fn test_shape_distances() {
+        let s1 = Shape::new(&points_image(8, 8, &[(1, 1), (2, 1)]), 0);
+        let s2 = Shape::new(&points_image(8, 8, &[(4, 1), (1, 6)]), 0);
+        assert_eq!(vec![(1, 1), (2, 1)], *s1.points());
+        assert_eq!((3.0 + 2.0) / 2.0, s1.directed_chamfer(&s2));
+        assert_eq!((2.0 + 5.0) / 2.0, s2.directed_chamfer(&s1));
+        assert_eq!(6.0, chamfer_distance(&s1, &s2));
+        assert_eq!(3.0, directed_hausdorff_distance(&s1, &s2));
+        assert_eq!(5.0, hausdorff_distance(&s1, &s2));
+        assert_eq!(3.5, modified_hausdorff_distance(&s1, &s2));
+        assert_eq!(0.0, hausdorff_distance(&s1, &s1));
+    }
+
+    #[test]
+    fn test_empty_shape() {
+        let empty = Shape::new(&points_image(3, 4, &[]), 0);
+        let dot = Shape::new(&points_image(3, 4, &[(0, 0)]), 0);
+        assert_eq!(5.0, dot.directed_hausdorff(&empty));
+        assert_eq!(0.0, empty.directed_hausdorff(&dot));
+        assert_eq!(5.0, hausdorff_distance(&empty, &dot));
+    }
+}
@@ -10,17 +10,53 @@
 //! assert_eq!(2 * (64 + 36 + 16 + 4), euclidean_distance(&img1, &img2));
 //! ```
 
-use crate::mnist_data::{Grid, Image};
+use crate::mnist_data::{Grid, Image, PixelImage};
+use crate::pixel::Pixel;
 
 // Using u32 so that it can be readily cast into an f64 in kmeans.
 pub fn euclidean_distance(img1: &Image, img2: &Image) -> u32 {
-    assert_eq!(img1.side(), img2.side());
+    assert_eq!(img1.width(), img2.width());
+    assert_eq!(img1.height(), img2.height());
     assert_eq!(img1.len(), img2.len());
     img1.x_y_iter()
         .map(|(x, y)| (img1.get(x, y) as i32 - img2.get(x, y) as i32).pow(2) as u32)
         .sum()
 }
 
+// For pixel types wider than u8, where the squared differences could overflow a u32.
+pub fn euclidean_distance_f64<P: Pixel + Into<f64>>(img1: &PixelImage<P>, img2: &PixelImage<P>) -> f64 {
+    assert_eq!(img1.width(), img2.width());
+    assert_eq!(img1.height(), img2.height());
+    assert_eq!(img1.len(), img2.len());
+    img1.x_y_iter()
+        .map(|(x, y)| (img1.get(x, y).into() - img2.get(x, y).into()).powi(2))
+        .sum()
+}
+
+// The smallest euclidean_distance() between img1 and img2 moved by up to radius pixels in each
+// direction, with pixels moved in from outside counting as 0. Each shift stops summing as soon as
+// it can no longer beat the best so far; the unshifted distance is tried first to set a low bar.
+pub fn shift_tolerant_distance(img1: &Image, img2: &Image, radius: usize) -> u32 {
+    assert_eq!(img1.width(), img2.width());
+    assert_eq!(img1.height(), img2.height());
+    let radius = radius as isize;
+    let shifts = (-radius..=radius).flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+        .filter(|shift| *shift != (0, 0));
+    shifts.fold(euclidean_distance(img1, img2), |best, (dx, dy)| {
+        let mut total = 0;
+        for y in 0..img1.height() {
+            for x in 0..img1.width() {
+                let moved = img2.option_get(x as isize + dx, y as isize + dy).unwrap_or(0);
+                total += (img1.get(x, y) as i32 - moved as i32).pow(2) as u32;
+            }
+            if total >= best {
+                return best;
+            }
+        }
+        total
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -32,4 +68,26 @@ mod tests {
         let img2 = Image::from_vec(&vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
         assert_eq!(2 * (64 + 36 + 16 + 4), euclidean_distance(&img1, &img2));
     }
+
+    #[test]
+    fn test_f64_values() {
+        let img1 = Image::from_vec(&vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let img2 = Image::from_vec(&vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(euclidean_distance(&img1, &img2) as f64, euclidean_distance_f64(&img1, &img2));
+        assert_eq!(euclidean_distance(&img1, &img2) as f64 / 4.0, euclidean_distance_f64(&img1.to_f32().map(|p| p / 2.0), &img2.to_f32().map(|p| p / 2.0)));
+        let wide1 = img1.to_u16().map(|p| p * 1000);
+        let wide2 = img2.to_u16().map(|p| p * 1000);
+        assert_eq!(euclidean_distance(&img1, &img2) as f64 * 1e6, euclidean_distance_f64(&wide1, &wide2));
+    }
+
+    #[test]
+    fn test_shift_tolerant() {
+        let img = Image::from_slice(&[0, 0, 0, 0, 0, 9, 8, 0, 0, 7, 6, 0, 0, 0, 0, 0], 4, 4);
+        let moved = crate::augmentation::translated(&img, 1, -1);
+        assert_eq!(euclidean_distance(&img, &moved), shift_tolerant_distance(&img, &moved, 0));
+        assert_eq!(0, shift_tolerant_distance(&img, &moved, 1));
+        assert_eq!(0, shift_tolerant_distance(&moved, &img, 2));
+        let far = crate::augmentation::translated(&img, 2, 0);
+        assert!(shift_tolerant_distance(&img, &far, 1) > 0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,369 @@
+//! On-disk cache of converted feature sets, so that repeated runs can skip slow conversions such as
+//! `kernel_stack_all`, `kernelize_single_image`, and `find_keypoints`.
+//!
+//! An entry holds the converted training and testing sets together. Several conversions mine
+//! kernels or draw BRIEF pairs at random, so both sets must come from the same draw to be comparable.
+//!
+//! An entry is keyed by data source, variant, and parameters. The key also includes a fingerprint
+//! of the input images, so shrunken, permuted, or otherwise altered inputs never reuse stale features.
+//! Each file starts with a magic number and a format version. Files from another version, or
+//! whose stored key differs, count as cache misses.
+
+use std::fs;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use bits::BitArray;
+use crate::mnist_data::{Image, PixelImage, Grid};
+use crate::idx::MAX_PREALLOCATION;
+use crate::pixel::{Pixel, Rgb};
+
+const CACHE_MAGIC: [u8; 4] = *b"DRFC";
+pub const CACHE_VERSION: u32 = 1;
+const CACHE_EXTENSION: &str = "features";
+
+// Converted training and testing sets, each paired with its labels.
+pub type FeatureSets<I> = (Vec<(u8,I)>, Vec<(u8,I)>);
+
+pub trait Cacheable: Sized {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()>;
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self>;
+}
+
+fn read_bytes<R: Read, const N: usize>(input: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl Cacheable for u8 {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&[*self])
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(read_bytes::<R, 1>(input)?[0])
+    }
+}
+
+impl Cacheable for u16 {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(u16::from_le_bytes(read_bytes(input)?))
+    }
+}
+
+impl Cacheable for u32 {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(u32::from_le_bytes(read_bytes(input)?))
+    }
+}
+
+// Stored as 64 bits, so that files do not depend on the platform's pointer width.
+impl Cacheable for usize {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&(*self as u64).to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let value = u64::from_le_bytes(read_bytes(input)?);
+        usize::try_from(value).map_err(|_| invalid_data(format!("Stored size {} does not fit in usize", value)))
+    }
+}
+
+impl Cacheable for f32 {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(f32::from_le_bytes(read_bytes(input)?))
+    }
+}
+
+impl Cacheable for Rgb {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&[self.r, self.g, self.b])
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let [r, g, b] = read_bytes(input)?;
+        Ok(Rgb::new(r, g, b))
+    }
+}
+
+impl Cacheable for String {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.len().write_to(output)?;
+        output.write_all(self.as_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(input)?;
+        let mut bytes = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        input.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+impl <A: Cacheable, B: Cacheable> Cacheable for (A, B) {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.0.write_to(output)?;
+        self.1.write_to(output)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok((A::read_from(input)?, B::read_from(input)?))
+    }
+}
+
+impl <T: Cacheable> Cacheable for Vec<T> {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.len().write_to(output)?;
+        self.iter().try_for_each(|item| item.write_to(output))
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(input)?;
+        let mut result = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        for _ in 0..len {
+            result.push(T::read_from(input)?);
+        }
+        Ok(result)
+    }
+}
+
+impl <P: Pixel + Cacheable> Cacheable for PixelImage<P> {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.width().write_to(output)?;
+        self.height().write_to(output)?;
+        self.pixels().iter().try_for_each(|p| p.write_to(output))
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let width = usize::read_from(input)?;
+        let height = usize::read_from(input)?;
+        let len = width.checked_mul(height).ok_or_else(|| invalid_data(format!("Image size {}x{} overflows", width, height)))?;
+        let mut pixels = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        for _ in 0..len {
+            pixels.push(P::read_from(input)?);
+        }
+        Ok(PixelImage::from_slice(&pixels, width, height))
+    }
+}
+
+// Bits are packed eight to a byte, lowest index in the lowest bit.
+impl Cacheable for BitArray {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.len().write_to(output)?;
+        let packed: Vec<u8> = (0..self.len()).step_by(8)
+            .map(|start| (start..(start + 8).min(self.len()))
+                .filter(|i| self.is_set(*i))
+                .fold(0, |byte, i| byte | (1 << (i - start))))
+            .collect();
+        output.write_all(&packed)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(input)?;
+        let mut result = BitArray::new();
+        let mut byte = 0;
+        for i in 0..len {
+            if i % 8 == 0 {
+                byte = u8::read_from(input)?;
+            }
+            result.add(byte & (1 << (i % 8)) != 0);
+        }
+        Ok(result)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// FNV-1a over labels, shapes, and pixels. Unlike std's hashers, it is stable across builds.
+pub fn fingerprint(images: &[(u8,Image)]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let mut hash = FNV_OFFSET;
+    let mut add = |bytes: &[u8]| for b in bytes {
+        hash = (hash ^ *b as u64).wrapping_mul(FNV_PRIME);
+    };
+    add(&(images.len() as u64).to_le_bytes());
+    for (label, img) in images.iter() {
+        add(&[*label]);
+        add(&(img.width() as u64).to_le_bytes());
+        add(&(img.height() as u64).to_le_bytes());
+        add(img.pixels());
+    }
+    hash
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheKey {
+    source: String,
+    variant: String,
+    parameters: String,
+    fingerprint: u64
+}
+
+impl CacheKey {
+    pub fn new(source: &str, variant: &str, parameters: &str, training: &[(u8,Image)], testing: &[(u8,Image)]) -> Self {
+        let fingerprint = fingerprint(training) ^ fingerprint(testing).rotate_left(1);
+        CacheKey {source: source.to_string(), variant: variant.to_string(), parameters: parameters.to_string(), fingerprint}
+    }
+
+    pub fn description(&self) -> String {
+        format!("{}/{}/{}/{:016x}", self.source, self.variant, self.parameters, self.fingerprint)
+    }
+
+    pub fn file_name(&self) -> String {
+        let readable: String = [self.source.as_str(), self.variant.as_str(), self.parameters.as_str()].iter()
+            .filter(|part| !part.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("-")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' {c} else {'_'})
+            .collect();
+        format!("{}-{:016x}.{}", readable, self.fingerprint, CACHE_EXTENSION)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FeatureCache {
+    dir: PathBuf
+}
+
+impl FeatureCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FeatureCache {dir: dir.as_ref().to_path_buf()}
+    }
+
+    pub fn path_for(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(key.file_name())
+    }
+
+    // Ok(None) means a miss: no file, or one written by another version or for another key.
+    pub fn load<I: Cacheable>(&self, key: &CacheKey) -> io::Result<Option<FeatureSets<I>>> {
+        let path = self.path_for(key);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut input = BufReader::new(fs::File::open(path)?);
+        if read_bytes::<_, 4>(&mut input)? != CACHE_MAGIC || u32::read_from(&mut input)? != CACHE_VERSION
+            || String::read_from(&mut input)? != key.description() {
+            return Ok(None);
+        }
+        Ok(Some(FeatureSets::read_from(&mut input)?))
+    }
+
+    // Writes to a temporary file first, so an interrupted run never leaves a truncated entry behind.
+    pub fn store<I: Cacheable>(&self, key: &CacheKey, training: &[(u8,I)], testing: &[(u8,I)]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path_for(key);
+        let temporary = path.with_extension(format!("{}.{}", CACHE_EXTENSION, std::process::id()));
+        let mut output = BufWriter::new(fs::File::create(&temporary)?);
+        output.write_all(&CACHE_MAGIC)?;
+        CACHE_VERSION.write_to(&mut output)?;
+        key.description().write_to(&mut output)?;
+        for set in [training, testing] {
+            set.len().write_to(&mut output)?;
+            set.iter().try_for_each(|(label, features)| {
+                label.write_to(&mut output)?;
+                features.write_to(&mut output)
+            })?;
+        }
+        output.flush()?;
+        drop(output);
+        fs::rename(temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn round_trip<T: Cacheable>(value: &T) -> T {
+        let mut bytes = Vec::new();
+        value.write_to(&mut bytes).unwrap();
+        let mut input = bytes.as_slice();
+        let result = T::read_from(&mut input).unwrap();
+        assert!(input.is_empty());
+        result
+    }
+
+    fn temp_cache(name: &str) -> FeatureCache {
+        FeatureCache::new(temp_path(name))
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let img = Image::test_pattern(5, 3);
+        assert_eq!(img, round_trip(&img));
+        assert_eq!(img.to_u16(), round_trip(&img.to_u16()));
+        assert_eq!(img.to_f32(), round_trip(&img.to_f32()));
+        assert_eq!(img.to_rgb(), round_trip(&img.to_rgb()));
+        let kernels = vec![Image::test_pattern(3, 3), Image::test_pattern(2, 4)];
+        assert_eq!(kernels, round_trip(&kernels));
+        let keypoints: Vec<(usize,usize)> = vec![(1, 2), (27, 0), (usize::MAX, 5)];
+        assert_eq!(keypoints, round_trip(&keypoints));
+        assert_eq!("emnist_letters".to_string(), round_trip(&"emnist_letters".to_string()));
+        for len in [0, 1, 8, 13] {
+            let mut bits = BitArray::new();
+            (0..len).for_each(|i| bits.add(i % 3 == 0));
+            let restored = round_trip(&bits);
+            assert_eq!(bits.len(), restored.len());
+            assert!((0..len).all(|i| bits.is_set(i) == restored.is_set(i)));
+        }
+        let mut truncated: &[u8] = &[2, 0, 0, 0, 0, 0, 0, 0, 7];
+        assert!(Vec::<u8>::read_from(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn test_keys() {
+        let training = vec![(1, Image::test_pattern(4, 4))];
+        let testing = vec![(2, Image::test_pattern(4, 4))];
+        let key = CacheKey::new("mnist", "compare_keypoints", "8 3 64", &training, &testing);
+        assert_eq!(key, CacheKey::new("mnist", "compare_keypoints", "8 3 64", &training, &testing));
+        assert!(key.file_name().starts_with("mnist-compare_keypoints-8_3_64-"));
+        assert_ne!(key, CacheKey::new("mnist", "compare_keypoints", "8 3 32", &training, &testing));
+        assert_ne!(key, CacheKey::new("mnist", "compare_keypoints", "8 3 64", &testing, &training));
+        assert_ne!(key, CacheKey::new("mnist", "compare_keypoints", "8 3 64", &training, &[(3, Image::test_pattern(4, 4))]));
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let cache = temp_cache("feature_cache");
+        let training = vec![(1, Image::test_pattern(4, 4)), (2, Image::test_pattern(4, 4).transposed())];
+        let testing = vec![(3, Image::test_pattern(4, 4))];
+        let key = CacheKey::new("mnist", "sobel_u16", "", &training, &testing);
+        assert!(cache.load::<PixelImage<u16>>(&key).unwrap().is_none());
+
+        let features = |set: &Vec<(u8,Image)>| set.iter().map(|(label, img)| (*label, img.to_u16())).collect::<Vec<_>>();
+        cache.store(&key, &features(&training), &features(&testing)).unwrap();
+        assert_eq!(Some((features(&training), features(&testing))), cache.load(&key).unwrap());
+
+        let other = CacheKey::new("fashion", "sobel_u16", "", &training, &testing);
+        fs::copy(cache.path_for(&key), cache.path_for(&other)).unwrap();
+        assert!(cache.load::<PixelImage<u16>>(&other).unwrap().is_none());
+
+        let mut bytes = fs::read(cache.path_for(&key)).unwrap();
+        bytes[4] = bytes[4].wrapping_add(1);
+        fs::write(cache.path_for(&key), &bytes).unwrap();
+        assert!(cache.load::<PixelImage<u16>>(&key).unwrap().is_none());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! The image distortion model (Keysers et al. 2007). Each pixel of one image may match any pixel
+//! of the other within a warp radius, and each match compares the square context windows around
+//! the two pixels rather than the pixels alone, which keeps neighboring pixels from warping
+//! independently. Like `kernel_patch::best_match_distance`, it tolerates small local deformations.
+//!
+//! Images are compared as stacks of channels: the intensities, or the signed Sobel gradients,
+//! which Keysers et al. found work best.
+
+use crate::mnist_data::{Image, Grid, PixelImage};
+use crate::sobel::gradients;
+
+pub type Channels = Vec<PixelImage<f32>>;
+
+pub fn intensity_channels(img: &Image) -> Channels {
+    vec![img.to_f32()]
+}
+
+pub fn gradient_channels(img: &Image) -> Channels {
+    let (x_gradients, y_gradients) = gradients(img);
+    vec![x_gradients, y_gradients]
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Idm {
+    // Each pixel may match one up to this many pixels away horizontally and vertically.
+    pub warp: usize,
+    // Context windows are 2 * context + 1 pixels on a side.
+    pub context: usize
+}
+
+impl Idm {
+    // Keysers et al.'s setting for 28x28 digits: a 5x5 warp range and 3x3 contexts.
+    pub fn new() -> Self {
+        Idm {warp: 2, context: 1}
+    }
+
+    // For every pixel of c2, the best squared context difference found among the pixels of c1
+    // within the warp range, summed. Only c1 is warped, so the distance is not symmetric.
+    pub fn distance(&self, c1: &Channels, c2: &Channels) -> f64 {
+        assert_eq!(c1.len(), c2.len());
+        let Some(first) = c2.first() else {return 0.0};
+        let (width, height) = (first.width(), first.height());
+        assert!(c1.iter().chain(c2.iter()).all(|c| (c.width(), c.height()) == (width, height)));
+        let (warp, context) = (self.warp as isize, self.context as isize);
+        first.x_y_iter().map(|(x, y)| {
+            let (x, y) = (x as isize, y as isize);
+            let mut best = f64::MAX;
+            for dy in -warp..=warp {
+                for dx in -warp..=warp {
+                    if !first.in_bounds(x + dx, y + dy) {
+                        continue;
+                    }
+                    let mut total = 0.0;
+                    for j in -context..=context {
+                        for i in -context..=context {
+                            for (channel1, channel2) in c1.iter().zip(c2.iter()) {
+                                let difference = clamped_get(channel1, x + dx + i, y + dy + j) - clamped_get(channel2, x + i, y + j);
+                                total += (difference * difference) as f64;
+                            }
+                        }
+                    }
+                    best = best.min(total);
+                }
+            }
+            best
+        }).sum()
+    }
+}
+
+impl Default for Idm {
+    fn default() -> Self {
+        Idm::new()
+    }
+}
+
+// Context windows that extend past the edge repeat the edge pixels.
+fn clamped_get(channel: &PixelImage<f32>, x: isize, y: isize) -> f32 {
+    let x = x.clamp(0, channel.width() as isize - 1) as usize;
+    let y = y.clamp(0, channel.height() as isize - 1) as usize;
+    channel.get(x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::augmentation::translated;
+
+    // A vertical stroke, two pixels wide, in a 9x9 image.
+    fn stroke(x_start: usize) -> Image {
+        let pixels: Vec<u8> = (0..81).map(|i| if (x_start..x_start + 2).contains(&(i % 9)) && (2..7).contains(&(i / 9)) {200} else {0}).collect();
+        Image::from_slice(&pixels, 9, 9)
+    }
+
+    #[test]
+    fn test_warp_tolerance() {
+        let (img, moved) = (stroke(3), stroke(4));
+        let rigid = Idm {warp: 0, context: 0};
+        assert_eq!(crate::euclidean_distance::euclidean_distance(&img, &moved) as f64,
+                   rigid.distance(&intensity_channels(&img), &intensity_channels(&moved)));
+        let idm = Idm::new();
+        assert_eq!(0.0, idm.distance(&intensity_channels(&img), &intensity_channels(&moved)));
+        assert_eq!(0.0, idm.distance(&gradient_channels(&img), &gradient_channels(&moved)));
+        let far = translated(&img, 4, 0);
+        assert!(idm.distance(&intensity_channels(&img), &intensity_channels(&far)) > 0.0);
+    }
+
+    #[test]
+    fn test_context() {
+        // Without context, a stroke can match scattered pixels of the same intensity.
+        let img = stroke(3);
+        let flat = Image::from_slice(&[200; 81], 9, 9);
+        let pointwise = Idm {warp: 2, context: 0}.distance(&intensity_channels(&img), &intensity_channels(&flat));
+        let windowed = Idm {warp: 2, context: 1}.distance(&intensity_channels(&img), &intensity_channels(&flat));
+        assert!(windowed > pointwise);
+        assert_eq!(0.0, Idm::new().distance(&intensity_channels(&img), &intensity_channels(&img)));
+    }
+}
@@ -0,0 +1,389 @@
+//! Reader for the IDX file format used by MNIST and its relatives.
+//!
+//! An IDX file starts with a 4-byte magic number: two zero bytes, a data type code, and the
+//! number of dimensions. Each dimension size follows as a big-endian `u32`, and then the data.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use flate2::bufread::GzDecoder;
+use memmap2::Mmap;
+use crate::mnist_data::Image;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// Buffers sized from a file header start no larger than this, so a corrupt header cannot request a
+// huge allocation up front. Shared with the feature cache, whose files carry lengths the same way.
+pub(crate) const MAX_PREALLOCATION: usize = 1 << 20;
+pub const GZIP_EXTENSION: &str = "gz";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdxDataType {
+    UnsignedByte, SignedByte, Short, Int, Float, Double
+}
+
+impl IdxDataType {
+    pub fn from_code(code: u8) -> Result<IdxDataType, IdxError> {
+        match code {
+            0x08 => Ok(IdxDataType::UnsignedByte),
+            0x09 => Ok(IdxDataType::SignedByte),
+            0x0B => Ok(IdxDataType::Short),
+            0x0C => Ok(IdxDataType::Int),
+            0x0D => Ok(IdxDataType::Float),
+            0x0E => Ok(IdxDataType::Double),
+            _ => Err(IdxError::UnknownDataType(code))
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            IdxDataType::UnsignedByte => 0x08,
+            IdxDataType::SignedByte => 0x09,
+            IdxDataType::Short => 0x0B,
+            IdxDataType::Int => 0x0C,
+            IdxDataType::Float => 0x0D,
+            IdxDataType::Double => 0x0E
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdxHeader {
+    pub data_type: IdxDataType,
+    pub dimensions: Vec<usize>
+}
+
+impl IdxHeader {
+    // Header sizes are untrusted, so a product too large for a usize is an error.
+    pub fn num_items(&self) -> Result<usize, IdxError> {
+        self.dimensions.iter()
+            .try_fold(1usize, |product, d| product.checked_mul(*d))
+            .ok_or_else(|| IdxError::TooLarge(self.dimensions.clone()))
+    }
+}
+
+#[derive(Debug)]
+pub enum IdxError {
+    Io(io::Error),
+    BadMagic([u8; 2]),
+    UnknownDataType(u8),
+    UnsupportedDataType(IdxDataType),
+    WrongDimensionCount {expected: usize, found: usize},
+    Truncated {expected_bytes: usize, found_bytes: usize},
+    TrailingBytes(usize),
+    TooLarge(Vec<usize>),
+    CountMismatch {images: usize, labels: usize}
+}
+
+impl fmt::Display for IdxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdxError::Io(e) => write!(f, "I/O error: {}", e),
+            IdxError::BadMagic(bytes) => write!(f, "Bad magic number: first two bytes are {:#04x} {:#04x}, not zero", bytes[0], bytes[1]),
+            IdxError::UnknownDataType(code) => write!(f, "Unknown IDX data type code {:#04x}", code),
+            IdxError::UnsupportedDataType(t) => write!(f, "Unsupported IDX data type {:?}; only unsigned bytes are supported", t),
+            IdxError::WrongDimensionCount {expected, found} => write!(f, "Expected {} dimensions, found {}", expected, found),
+            IdxError::Truncated {expected_bytes, found_bytes} => write!(f, "File truncated: expected {} data bytes, found {}", expected_bytes, found_bytes),
+            IdxError::TrailingBytes(n) => write!(f, "{} unexpected bytes after the data", n),
+            IdxError::TooLarge(dimensions) => write!(f, "Dimensions {:?} describe more items than can be addressed", dimensions),
+            IdxError::CountMismatch {images, labels} => write!(f, "{} images but {} labels", images, labels)
+        }
+    }
+}
+
+impl std::error::Error for IdxError {}
+
+impl From<io::Error> for IdxError {
+    fn from(e: io::Error) -> Self {
+        IdxError::Io(e)
+    }
+}
+
+impl From<IdxError> for io::Error {
+    fn from(e: IdxError) -> Self {
+        match e {
+            IdxError::Io(e) => e,
+            other => io::Error::new(io::ErrorKind::InvalidData, other)
+        }
+    }
+}
+
+// Files may be stored either raw or gzip-compressed, as in the official distributions.
+pub fn existing_path(dir: &Path, file_name: &str) -> Option<PathBuf> {
+    let raw = dir.join(file_name);
+    let compressed = dir.join(format!("{}.{}", file_name, GZIP_EXTENSION));
+    if raw.is_file() {
+        Some(raw)
+    } else if compressed.is_file() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    decompressing(BufReader::new(fs::File::open(path)?))
+}
+
+// Checks for the gzip magic bytes without consuming them, so raw input streams through untouched.
+pub fn decompressing<R: BufRead + 'static>(mut input: R) -> io::Result<Box<dyn Read>> {
+    if is_gzipped(input.fill_buf()?) {
+        Ok(Box::new(GzDecoder::new(input)))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+pub fn read_header<R: Read>(input: &mut R) -> Result<IdxHeader, IdxError> {
+    let mut magic = [0; 4];
+    read_header_bytes(input, &mut magic)?;
+    if magic[0] != 0 || magic[1] != 0 {
+        return Err(IdxError::BadMagic([magic[0], magic[1]]));
+    }
+    let data_type = IdxDataType::from_code(magic[2])?;
+    let mut dimensions = Vec::new();
+    for _ in 0..magic[3] {
+        let mut size = [0; 4];
+        read_header_bytes(input, &mut size)?;
+        dimensions.push(u32::from_be_bytes(size) as usize);
+    }
+    Ok(IdxHeader {data_type, dimensions})
+}
+
+fn read_header_bytes<R: Read>(input: &mut R, buffer: &mut [u8]) -> Result<(), IdxError> {
+    input.read_exact(buffer).map_err(|e| if e.kind() == io::ErrorKind::UnexpectedEof {
+        IdxError::Truncated {expected_bytes: buffer.len(), found_bytes: 0}
+    } else {
+        IdxError::Io(e)
+    })
+}
+
+fn check_header(header: &IdxHeader, num_dimensions: usize) -> Result<(), IdxError> {
+    if header.data_type != IdxDataType::UnsignedByte {
+        Err(IdxError::UnsupportedDataType(header.data_type))
+    } else if header.dimensions.len() != num_dimensions {
+        Err(IdxError::WrongDimensionCount {expected: num_dimensions, found: header.dimensions.len()})
+    } else {
+        Ok(())
+    }
+}
+
+fn check_length(expected_bytes: usize, found_bytes: usize) -> Result<(), IdxError> {
+    if found_bytes < expected_bytes {
+        Err(IdxError::Truncated {expected_bytes, found_bytes})
+    } else if found_bytes > expected_bytes {
+        Err(IdxError::TrailingBytes(found_bytes - expected_bytes))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoadMode {
+    Buffered, MemoryMapped
+}
+
+pub fn load_labels<P: AsRef<Path>>(path: P, mode: LoadMode) -> Result<Vec<u8>, IdxError> {
+    match mode {
+        LoadMode::Buffered => read_labels(open(path)?),
+        LoadMode::MemoryMapped => {
+            let mapped = map(path)?;
+            if is_gzipped(&mapped) {
+                read_labels(GzDecoder::new(&mapped[..]))
+            } else {
+                parse_labels(&mapped)
+            }
+        }
+    }
+}
+
+pub fn load_images<P: AsRef<Path>>(path: P, mode: LoadMode) -> Result<Vec<Image>, IdxError> {
+    match mode {
+        LoadMode::Buffered => read_images(open(path)?),
+        LoadMode::MemoryMapped => {
+            let mapped = map(path)?;
+            if is_gzipped(&mapped) {
+                read_images(GzDecoder::new(&mapped[..]))
+            } else {
+                parse_images(&mapped)
+            }
+        }
+    }
+}
+
+fn map<P: AsRef<Path>>(path: P) -> io::Result<Mmap> {
+    let file = fs::File::open(path)?;
+    // Safety: the data files are only ever read, and not expected to change while loaded.
+    unsafe { Mmap::map(&file) }
+}
+
+fn is_gzipped(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+pub fn read_labels<R: Read>(mut input: R) -> Result<Vec<u8>, IdxError> {
+    let header = read_header(&mut input)?;
+    check_header(&header, 1)?;
+    let num_items = header.num_items()?;
+    let mut labels = Vec::with_capacity(num_items.min(MAX_PREALLOCATION));
+    input.read_to_end(&mut labels)?;
+    check_length(num_items, labels.len())?;
+    Ok(labels)
+}
+
+pub fn parse_labels(mut bytes: &[u8]) -> Result<Vec<u8>, IdxError> {
+    let header = read_header(&mut bytes)?;
+    check_header(&header, 1)?;
+    check_length(header.num_items()?, bytes.len())?;
+    Ok(bytes.to_vec())
+}
+
+pub fn read_images<R: Read>(mut input: R) -> Result<Vec<Image>, IdxError> {
+    let header = read_header(&mut input)?;
+    check_header(&header, 3)?;
+    let mut pixels = Vec::with_capacity(header.num_items()?.min(MAX_PREALLOCATION));
+    input.read_to_end(&mut pixels)?;
+    split_images(&header, &pixels)
+}
+
+pub fn parse_images(mut bytes: &[u8]) -> Result<Vec<Image>, IdxError> {
+    let header = read_header(&mut bytes)?;
+    check_header(&header, 3)?;
+    split_images(&header, bytes)
+}
+
+fn split_images(header: &IdxHeader, pixels: &[u8]) -> Result<Vec<Image>, IdxError> {
+    check_length(header.num_items()?, pixels.len())?;
+    let (count, rows, cols) = (header.dimensions[0], header.dimensions[1], header.dimensions[2]);
+    if rows * cols == 0 {
+        Ok(vec![Image::new(); count])
+    } else {
+        Ok(pixels.chunks_exact(rows * cols)
+            .map(|image_pixels| Image::from_slice(image_pixels, cols, rows))
+            .collect())
+    }
+}
+
+pub fn zip_labels(labels: Vec<u8>, images: Vec<Image>) -> Result<Vec<(u8,Image)>, IdxError> {
+    if labels.len() != images.len() {
+        Err(IdxError::CountMismatch {images: images.len(), labels: labels.len()})
+    } else {
+        Ok(labels.into_iter().zip(images).collect())
+    }
+}
+
+#[cfg(test)]
+pub fn idx_bytes(dimensions: &[u32], data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0, 0, IdxDataType::UnsignedByte.code(), dimensions.len() as u8];
+    for d in dimensions {
+        bytes.extend_from_slice(&d.to_be_bytes());
+    }
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::mnist_data::Grid;
+
+    #[test]
+    fn test_header() {
+        let bytes = idx_bytes(&[2, 3, 3], &[]);
+        let header = read_header(&mut bytes.as_slice()).unwrap();
+        assert_eq!(IdxDataType::UnsignedByte, header.data_type);
+        assert_eq!(vec![2, 3, 3], header.dimensions);
+        assert_eq!(18, header.num_items().unwrap());
+    }
+
+    #[test]
+    fn test_read_images() {
+        let pixels: Vec<u8> = (0..18).collect();
+        let images = read_images(idx_bytes(&[2, 3, 3], &pixels).as_slice()).unwrap();
+        assert_eq!(2, images.len());
+        assert_eq!(3, images[0].side());
+        assert_eq!(5, images[0].get(2, 1));
+        assert_eq!(9, images[1].get(0, 0));
+    }
+
+    #[test]
+    fn test_rectangular_images() {
+        let pixels: Vec<u8> = (0..12).collect();
+        let images = read_images(idx_bytes(&[2, 2, 3], &pixels).as_slice()).unwrap();
+        assert_eq!((3, 2), (images[0].width(), images[0].height()));
+        assert_eq!(5, images[0].get(2, 1));
+        assert_eq!(11, images[1].get(2, 1));
+    }
+
+    #[test]
+    fn test_parse_matches_read() {
+        let pixels: Vec<u8> = (0..32).collect();
+        let bytes = idx_bytes(&[2, 4, 4], &pixels);
+        assert_eq!(read_images(bytes.as_slice()).unwrap(), parse_images(&bytes).unwrap());
+        let bytes = idx_bytes(&[4], &[1, 2, 3, 4]);
+        assert_eq!(read_labels(bytes.as_slice()).unwrap(), parse_labels(&bytes).unwrap());
+        assert!(matches!(parse_labels(&idx_bytes(&[5], &[1, 2, 3, 4])), Err(IdxError::Truncated {..})));
+    }
+
+    #[test]
+    fn test_memory_mapped() {
+        let path = temp_path("mmap");
+        let pixels: Vec<u8> = (0..18).collect();
+        fs::write(&path, idx_bytes(&[2, 3, 3], &pixels)).unwrap();
+        let buffered = load_images(&path, LoadMode::Buffered).unwrap();
+        let mapped = load_images(&path, LoadMode::MemoryMapped).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(buffered, mapped);
+        assert_eq!(17, mapped[1].get(2, 2));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut bytes = idx_bytes(&[2], &[1, 2]);
+        bytes[0] = 1;
+        assert!(matches!(read_labels(bytes.as_slice()), Err(IdxError::BadMagic([1, 0]))));
+    }
+
+    #[test]
+    fn test_wrong_type_and_dimensions() {
+        let mut bytes = idx_bytes(&[2], &[1, 2]);
+        bytes[2] = 0x0C;
+        assert!(matches!(read_labels(bytes.as_slice()), Err(IdxError::UnsupportedDataType(IdxDataType::Int))));
+        assert!(matches!(read_images(idx_bytes(&[2], &[1, 2]).as_slice()), Err(IdxError::WrongDimensionCount {expected: 3, found: 1})));
+    }
+
+    #[test]
+    fn test_length_checks() {
+        assert!(matches!(read_labels(idx_bytes(&[3], &[1, 2]).as_slice()), Err(IdxError::Truncated {expected_bytes: 3, found_bytes: 2})));
+        assert!(matches!(read_labels(idx_bytes(&[1], &[1, 2]).as_slice()), Err(IdxError::TrailingBytes(1))));
+        assert!(matches!(read_images(idx_bytes(&[2, 2, 2], &[0; 7]).as_slice()), Err(IdxError::Truncated {expected_bytes: 8, found_bytes: 7})));
+        assert!(matches!(read_header(&mut [0, 0, 8].as_slice()), Err(IdxError::Truncated {..})));
+        assert!(matches!(read_labels(idx_bytes(&[u32::MAX], &[1]).as_slice()), Err(IdxError::Truncated {found_bytes: 1, ..})));
+        assert!(matches!(read_images(idx_bytes(&[u32::MAX; 3], &[]).as_slice()), Err(IdxError::TooLarge(_))));
+        assert!(matches!(parse_images(&idx_bytes(&[u32::MAX; 3], &[])), Err(IdxError::TooLarge(_))));
+    }
+
+    #[test]
+    fn test_gzip() {
+        let raw = idx_bytes(&[3], &[4, 5, 6]);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &raw).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_ne!(raw, compressed);
+        for bytes in [raw, compressed] {
+            let labels = read_labels(decompressing(io::Cursor::new(bytes)).unwrap()).unwrap();
+            assert_eq!(vec![4, 5, 6], labels);
+        }
+    }
+
+    #[test]
+    fn test_count_mismatch() {
+        let images = read_images(idx_bytes(&[2, 1, 1], &[5, 6]).as_slice()).unwrap();
+        assert!(matches!(zip_labels(vec![1, 2, 3], images.clone()), Err(IdxError::CountMismatch {images: 2, labels: 3})));
+        let labeled = zip_labels(vec![7, 8], images).unwrap();
+        assert_eq!(8, labeled[1].0);
+        assert_eq!(6, labeled[1].1.get(0, 0));
+    }
+}
@@ -0,0 +1,183 @@
+//! Labeled image sets stored as folders of image files, laid out as `root/<class>/*.png|*.pgm`,
+//! which is the layout `image_io::export_labeled` writes.
+//!
+//! Labels are still a bare `u8`, so each set keeps the mapping from labels to class names. If every
+//! class directory name is a number that fits in a `u8`, that number is the label; otherwise the
+//! class names are sorted and numbered from zero.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::image_io::ImageFormat;
+use crate::mnist_data::{Image, Grid};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabeledImages {
+    pub images: Vec<(u8,Image)>,
+    pub class_names: BTreeMap<u8,String>
+}
+
+impl LabeledImages {
+    pub fn num_classes(&self) -> usize {
+        self.class_names.len()
+    }
+
+    pub fn class_name(&self, label: u8) -> Option<&str> {
+        self.class_names.get(&label).map(|name| name.as_str())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn class_directories(root: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+pub fn assign_labels(class_names: &[String]) -> io::Result<BTreeMap<u8,String>> {
+    let numeric: Option<Vec<u8>> = class_names.iter().map(|name| name.parse().ok()).collect();
+    match numeric {
+        Some(labels) => {
+            let mut assigned = BTreeMap::new();
+            for (label, name) in labels.into_iter().zip(class_names.iter()) {
+                if let Some(other) = assigned.insert(label, name.clone()) {
+                    return Err(invalid_data(format!("classes \"{}\" and \"{}\" would both get label {}", other, name, label)));
+                }
+            }
+            Ok(assigned)
+        }
+        None if class_names.len() > u8::MAX as usize + 1 =>
+            Err(invalid_data(format!("{} classes found; at most {} fit in a u8 label", class_names.len(), u8::MAX as usize + 1))),
+        None => Ok(class_names.iter().enumerate().map(|(label, name)| (label as u8, name.clone())).collect())
+    }
+}
+
+// Labels and class names come from the class directories under root.
+pub fn load_image_folder(root: &Path, side: Option<usize>) -> io::Result<LabeledImages> {
+    let class_names = assign_labels(&class_directories(root)?)?;
+    load_image_folder_with(root, &class_names, side)
+}
+
+// Uses an existing label mapping, so that a testing set gets the same labels as its training set.
+pub fn load_image_folder_with(root: &Path, class_names: &BTreeMap<u8,String>, side: Option<usize>) -> io::Result<LabeledImages> {
+    let labels: BTreeMap<&str,u8> = class_names.iter().map(|(label, name)| (name.as_str(), *label)).collect();
+    let mut images = Vec::new();
+    for name in class_directories(root)? {
+        let label = *labels.get(name.as_str())
+            .ok_or_else(|| invalid_data(format!("{}: class \"{}\" is not among the known classes", root.display(), name)))?;
+        for path in image_files(&root.join(&name))? {
+            let img = Image::load(&path)?;
+            let img = match side {
+                None => img,
+                Some(side) => resized(&img, side)
+                    .ok_or_else(|| invalid_data(format!("{}: cannot resize a {}x{} image to side {}; images must be square, with a side that {} divides or is a multiple of",
+                        path.display(), img.width(), img.height(), side, side)))?
+            };
+            images.push((label, img));
+        }
+    }
+    Ok(LabeledImages {images, class_names: class_names.clone()})
+}
+
+fn image_files(dir: &Path) -> io::Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && ImageFormat::from_path(&path).is_some() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Scales a square image by a whole-number factor, so that its side becomes side.
+pub fn resized(img: &Image, side: usize) -> Option<Image> {
+    let current = img.width();
+    if current != img.height() || current == 0 || side == 0 {
+        None
+    } else if current == side {
+        Some(img.clone())
+    } else if current > side && current.is_multiple_of(side) {
+        Some(img.shrunken(current / side))
+    } else if current < side && side.is_multiple_of(current) {
+        Some(img.enlarged(side / current))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::image_io::export_labeled;
+
+    #[test]
+    fn test_assign_labels() {
+        let numeric = assign_labels(&["3".to_string(), "7".to_string()]).unwrap();
+        assert_eq!(vec![(3, "3".to_string()), (7, "7".to_string())], numeric.into_iter().collect::<Vec<_>>());
+        let named = assign_labels(&["alpha".to_string(), "beta".to_string(), "7".to_string()]).unwrap();
+        assert_eq!(Some(&"7".to_string()), named.get(&2));
+        let too_many: Vec<String> = (0..300).map(|i| format!("c{}", i)).collect();
+        assert!(assign_labels(&too_many).is_err());
+        assert!(assign_labels(&["01".to_string(), "1".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_resized() {
+        let img = Image::test_pattern(4, 4);
+        assert_eq!(Some((2, 2)), resized(&img, 2).map(|r| (r.width(), r.height())));
+        assert_eq!(Some(img.enlarged(2)), resized(&img, 8));
+        assert_eq!(Some(img.clone()), resized(&img, 4));
+        assert_eq!(None, resized(&img, 6));
+        assert_eq!(None, resized(&Image::test_pattern(4, 2), 2));
+        let large = Image::from_slice(&[200; 560 * 560], 560, 560);
+        assert_eq!(Some(Image::from_slice(&[200; 28 * 28], 28, 28)), resized(&large, 28));
+    }
+
+    #[test]
+    fn test_round_trip_numeric() {
+        let root = temp_path("numeric_folder");
+        let images = vec![(3, Image::test_pattern(4, 4)), (7, Image::test_pattern(4, 4)), (3, Image::test_pattern(4, 4))];
+        export_labeled(&images, &root, ImageFormat::Pgm, 1).unwrap();
+        let loaded = load_image_folder(&root, None).unwrap();
+        assert_eq!(2, loaded.num_classes());
+        assert_eq!(Some("7"), loaded.class_name(7));
+        assert_eq!(vec![3, 3, 7], loaded.images.iter().map(|(label, _)| *label).collect::<Vec<_>>());
+        assert_eq!(images[0].1, loaded.images[0].1);
+
+        let scaled = load_image_folder(&root, Some(8)).unwrap();
+        assert!(scaled.images.iter().all(|(_, img)| img.width() == 8 && img.height() == 8));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_named_classes() {
+        let root = temp_path("named_folder");
+        for (name, fill) in [("circle", 10), ("square", 200)] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            Image::from_slice(&[fill; 4], 2, 2).save(root.join(name).join("a.png"), 1).unwrap();
+        }
+        fs::write(root.join("square").join("notes.txt"), "ignored").unwrap();
+        let loaded = load_image_folder(&root, None).unwrap();
+        assert_eq!(Some("square"), loaded.class_name(1));
+        assert_eq!(2, loaded.images.len());
+        assert_eq!((1, 200), (loaded.images[1].0, loaded.images[1].1.get(0, 0)));
+
+        let mut only_circle = BTreeMap::new();
+        only_circle.insert(0, "circle".to_string());
+        assert!(load_image_folder_with(&root, &only_circle, None).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
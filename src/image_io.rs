@@ -0,0 +1,211 @@
+//! Reading and writing `Image` as binary PGM (P5) and 8-bit grayscale PNG, so that images,
+//! Sobel edge images, mined kernels, and pyramid levels can be inspected with ordinary tools.
+//!
+//! Images of other pixel types should be converted to `Image` explicitly before saving.
+
+use std::fs;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use crate::mnist_data::{Image, Grid};
+use crate::pixel::Rgb;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Pgm, Png
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "pgm" => Some(ImageFormat::Pgm),
+            "png" => Some(ImageFormat::Png),
+            _ => None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Pgm => "pgm",
+            ImageFormat::Png => "png"
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn format_of(path: &Path) -> io::Result<ImageFormat> {
+    ImageFormat::from_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: not a .pgm or .png file", path.display())))
+}
+
+impl Image {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let path = path.as_ref();
+        let input = BufReader::new(fs::File::open(path)?);
+        match format_of(path)? {
+            ImageFormat::Pgm => Image::read_pgm(input),
+            ImageFormat::Png => Image::read_png(input)
+        }
+    }
+
+    // Each pixel becomes a scale x scale block, which makes tiny kernels visible.
+    pub fn save<P: AsRef<Path>>(&self, path: P, scale: usize) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = format_of(path)?;
+        let img = if scale > 1 {self.enlarged(scale)} else {self.clone()};
+        let mut output = BufWriter::new(fs::File::create(path)?);
+        match format {
+            ImageFormat::Pgm => img.write_pgm(&mut output)?,
+            ImageFormat::Png => img.write_png(&mut output)?
+        }
+        output.flush()
+    }
+
+    pub fn read_pgm<R: Read>(mut input: R) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let mut header = PgmHeaderParser {bytes: &bytes, position: 0};
+        if header.token()? != "P5" {
+            return Err(invalid_data("Not a binary (P5) PGM file".to_string()));
+        }
+        let width = header.number()?;
+        let height = header.number()?;
+        let max_value = header.number()?;
+        if max_value == 0 || max_value > u8::MAX as usize {
+            return Err(invalid_data(format!("Unsupported PGM maximum value {}; only 8-bit images are supported", max_value)));
+        }
+        // Exactly one whitespace byte separates the header from the pixels.
+        let start = header.position + 1;
+        let pixels = bytes.get(start..start + width * height)
+            .ok_or_else(|| invalid_data(format!("PGM file truncated: expected {} pixels", width * height)))?;
+        Ok(Image::from_slice(pixels, width, height))
+    }
+
+    pub fn write_pgm<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        write!(output, "P5\n{} {}\n{}\n", self.width(), self.height(), u8::MAX)?;
+        output.write_all(self.pixels())
+    }
+
+    // Color and alpha channels are reduced to grayscale luma.
+    pub fn read_png<R: Read>(input: R) -> io::Result<Image> {
+        let mut decoder = png::Decoder::new(input);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let bytes = &buffer[..info.buffer_size()];
+        let pixels: Vec<u8> = match info.color_type {
+            png::ColorType::Grayscale => bytes.to_vec(),
+            png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).map(|p| p[0]).collect(),
+            png::ColorType::Rgb => bytes.chunks_exact(3).map(|p| Rgb::new(p[0], p[1], p[2]).luma()).collect(),
+            png::ColorType::Rgba => bytes.chunks_exact(4).map(|p| Rgb::new(p[0], p[1], p[2]).luma()).collect(),
+            png::ColorType::Indexed => return Err(invalid_data("Indexed PNG was not expanded".to_string()))
+        };
+        Ok(Image::from_slice(&pixels, info.width as usize, info.height as usize))
+    }
+
+    pub fn write_png<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(output, self.width() as u32, self.height() as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.pixels())?;
+        Ok(())
+    }
+}
+
+struct PgmHeaderParser<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> PgmHeaderParser<'a> {
+    fn token(&mut self) -> io::Result<String> {
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b'#') => while self.bytes.get(self.position).is_some_and(|b| *b != b'\n') {
+                    self.position += 1;
+                },
+                Some(b) if b.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err(invalid_data("PGM header truncated".to_string()))
+            }
+        }
+        let start = self.position;
+        while self.bytes.get(self.position).is_some_and(|b| !b.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        Ok(String::from_utf8_lossy(&self.bytes[start..self.position]).into_owned())
+    }
+
+    fn number(&mut self) -> io::Result<usize> {
+        let token = self.token()?;
+        token.parse().map_err(|_| invalid_data(format!("Bad number \"{}\" in PGM header", token)))
+    }
+}
+
+// Writes root/<label>/<index>.<extension> for every image, numbered by position in the data set.
+pub fn export_labeled<P: AsRef<Path>>(images: &[(u8,Image)], root: P, format: ImageFormat, scale: usize) -> io::Result<()> {
+    let root = root.as_ref();
+    for (index, (label, img)) in images.iter().enumerate() {
+        let dir = root.join(label.to_string());
+        fs::create_dir_all(&dir)?;
+        img.save(dir.join(format!("{:05}.{}", index, format.extension())), scale)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn test_pgm_round_trip() {
+        let img = Image::test_pattern(5, 3);
+        let mut bytes = Vec::new();
+        img.write_pgm(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"P5\n5 3\n255\n"));
+        assert_eq!(img, Image::read_pgm(bytes.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_pgm_header() {
+        let mut bytes = b"P5 # a comment\n# another\n2\t2\n255\n".to_vec();
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(Image::from_slice(&[1, 2, 3, 4], 2, 2), Image::read_pgm(bytes.as_slice()).unwrap());
+        assert!(Image::read_pgm(&b"P5\n2 2\n255\n\x01\x02"[..]).is_err());
+        assert!(Image::read_pgm(&b"P2\n2 2\n255\n1 2 3 4"[..]).is_err());
+        assert!(Image::read_pgm(&b"P5\n1 1\n65535\n\x00\x01"[..]).is_err());
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let img = Image::test_pattern(7, 4);
+        let mut bytes = Vec::new();
+        img.write_png(&mut bytes).unwrap();
+        assert_eq!(img, Image::read_png(bytes.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_save_scaled_and_export() {
+        let root = temp_path("export");
+        let kernel = Image::from_slice(&[0, 255, 255, 0], 2, 2);
+        let images = vec![(3, kernel.clone()), (7, Image::test_pattern(4, 4)), (3, Image::test_pattern(2, 6))];
+        export_labeled(&images, &root, ImageFormat::Png, 4).unwrap();
+        let loaded = Image::load(root.join("3").join("00000.png")).unwrap();
+        assert_eq!((8, 8), (loaded.width(), loaded.height()));
+        assert_eq!(kernel.enlarged(4), loaded);
+        assert!(root.join("7").join("00001.png").is_file());
+        assert!(root.join("3").join("00002.png").is_file());
+
+        let pgm = root.join("kernel.pgm");
+        kernel.save(&pgm, 1).unwrap();
+        assert_eq!(kernel, Image::load(&pgm).unwrap());
+        assert!(kernel.save(root.join("kernel.bmp"), 1).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
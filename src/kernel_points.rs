@@ -3,15 +3,24 @@ use crate::kernel_patch::kernelize_single_image;
 use crate::euclidean_distance::euclidean_distance;
 
 pub fn find_keypoints(img: &Image, num_kernels: usize, kernel_size: usize, num_keypoints: usize) -> Vec<(usize, usize)> {
+    find_matched_keypoints(img, num_kernels, kernel_size, num_keypoints).iter()
+        .map(|(_, x, y, _)| (*x, *y))
+        .collect()
+}
+
+// The keypoints of find_keypoints, each as (distance to its best-matching kernel, x, y, index of
+// that kernel), best matches first.
+pub fn find_matched_keypoints(img: &Image, num_kernels: usize, kernel_size: usize, num_keypoints: usize) -> Vec<(u32, usize, usize, usize)> {
     let kernels = kernelize_single_image(img, num_kernels, kernel_size);
-    let mut dists: Vec<(u32, usize, usize)> = img.x_y_step_iter(1)
-        .map(|(x, y)| (best_matching_kernel_distance(&kernels, img, x, y), x, y))
+    let mut dists: Vec<(u32, usize, usize, usize)> = img.x_y_step_iter(1)
+        .map(|(x, y)| {
+            let (distance, kernel) = best_matching_kernel(&kernels, img, x, y);
+            (distance, x, y, kernel)
+        })
         .collect();
     dists.sort();
-    dists.iter()
-        .take(num_keypoints)
-        .map(|(_, x, y)| (*x, *y))
-        .collect()
+    dists.truncate(num_keypoints);
+    dists
 }
 
 pub fn closest_for_all(group1: &Vec<(usize,usize)>, group2: &Vec<(usize,usize)>) -> usize {
@@ -37,8 +46,33 @@ pub fn squared_diff(x1: usize, x2: usize) -> usize {
 }
 
 pub fn best_matching_kernel_distance(kernels: &Vec<Image>, img: &Image, x: usize, y: usize) -> u32 {
+    best_matching_kernel(kernels, img, x, y).0
+}
+
+// The distance to the kernel that best matches the pixels centered at (x, y), and its index.
+pub fn best_matching_kernel(kernels: &Vec<Image>, img: &Image, x: usize, y: usize) -> (u32, usize) {
     (0..kernels.len())
-        .map(|i| euclidean_distance(&img.subimage(x, y, kernels[i].side()), &kernels[i]))
+        .map(|i| (euclidean_distance(&img.subimage(x, y, kernels[i].side()), &kernels[i]), i))
         .min()
         .unwrap()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{rectangular_image, RECT_WIDTH, RECT_HEIGHT};
+
+    #[test]
+    fn test_keypoints_in_bounds() {
+        let keypoints = find_keypoints(&rectangular_image(), 4, 3, 10);
+        assert_eq!(10, keypoints.len());
+        assert!(keypoints.iter().all(|(x, y)| *x < RECT_WIDTH && *y < RECT_HEIGHT));
+    }
+
+    #[test]
+    fn test_closest_for_all() {
+        let keypoints = find_keypoints(&rectangular_image(), 4, 3, 10);
+        assert_eq!(0, closest_for_all(&keypoints, &keypoints));
+        assert_eq!(2 * 9, closest_for_all(&vec![(31, 19)], &vec![(28, 19)]));
+    }
+}
@@ -0,0 +1,244 @@
+//! Keypoints that record what they look like as well as where they are. `kernel_points` keeps only
+//! coordinates, so its distances ignore appearance; a `Keypoint` here also keeps its best-matching
+//! kernel, how closely that kernel matches, and a descriptor of the patch around it.
+//!
+//! Kernels are found separately for every image, so kernel indices mean nothing across images and
+//! matching uses only positions and descriptors. Every descriptor difference lies between 0 and 1,
+//! so the appearance weight is the squared pixel distance that a complete mismatch costs.
+
+use std::io;
+use std::io::{Read, Write};
+use std::f32::consts::PI;
+use bits::BitArray;
+use crate::brief::Descriptor;
+use crate::euclidean_distance::euclidean_distance;
+use crate::feature_cache::Cacheable;
+use crate::kernel_points::find_matched_keypoints;
+use crate::mnist_data::{Image, Grid, ImageIterator, PixelImage};
+use crate::sobel::gradients;
+
+pub const DESCRIPTOR_SIDE: usize = 7;
+pub const HISTOGRAM_BINS: usize = 8;
+pub const APPEARANCE_WEIGHT: f64 = 16.0;
+
+// How to describe the square patch centered on each keypoint.
+#[derive(Clone)]
+pub enum DescriptorKind {
+    // The patch's pixels, for a patch of this side.
+    Patch(usize),
+    // BRIEF bits; the patch is the descriptor's size, which must be square.
+    Brief(Descriptor),
+    // Sobel gradient orientations over a patch of this side, weighted by magnitude, in this many bins.
+    GradientHistogram {side: usize, bins: usize}
+}
+
+#[derive(Clone)]
+pub enum KeypointDescriptor {
+    Patch(Image),
+    Brief(BitArray),
+    // Normalized to sum to 1, or all 0 where the patch is flat.
+    GradientHistogram(Vec<f32>)
+}
+
+impl KeypointDescriptor {
+    // Between 0 for identical patches and 1 for the most different ones. Both descriptors must be
+    // of the same kind and size.
+    pub fn difference(&self, other: &KeypointDescriptor) -> f64 {
+        match (self, other) {
+            (KeypointDescriptor::Patch(p1), KeypointDescriptor::Patch(p2)) =>
+                euclidean_distance(p1, p2) as f64 / (p1.len() as f64 * 255.0 * 255.0),
+            (KeypointDescriptor::Brief(b1), KeypointDescriptor::Brief(b2)) => {
+                assert_eq!(b1.len(), b2.len());
+                if b1.len() == 0 {0.0} else {bits::distance(b1, b2) as f64 / b1.len() as f64}
+            }
+            (KeypointDescriptor::GradientHistogram(h1), KeypointDescriptor::GradientHistogram(h2)) => {
+                assert_eq!(h1.len(), h2.len());
+                // A flat patch is as different as possible from any but another flat patch.
+                let (flat1, flat2) = (h1.iter().all(|c| *c == 0.0), h2.iter().all(|c| *c == 0.0));
+                if flat1 || flat2 {
+                    if flat1 == flat2 {0.0} else {1.0}
+                } else {
+                    h1.iter().zip(h2.iter()).map(|(c1, c2)| (c1 - c2).abs() as f64).sum::<f64>() / 2.0
+                }
+            }
+            _ => panic!("Cannot compare keypoint descriptors of different kinds")
+        }
+    }
+}
+
+impl Cacheable for KeypointDescriptor {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        match self {
+            KeypointDescriptor::Patch(patch) => {
+                0u8.write_to(output)?;
+                patch.write_to(output)
+            }
+            KeypointDescriptor::Brief(bits) => {
+                1u8.write_to(output)?;
+                bits.write_to(output)
+            }
+            KeypointDescriptor::GradientHistogram(histogram) => {
+                2u8.write_to(output)?;
+                histogram.write_to(output)
+            }
+        }
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        match u8::read_from(input)? {
+            0 => Ok(KeypointDescriptor::Patch(Image::read_from(input)?)),
+            1 => Ok(KeypointDescriptor::Brief(BitArray::read_from(input)?)),
+            2 => Ok(KeypointDescriptor::GradientHistogram(Vec::read_from(input)?)),
+            tag => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown keypoint descriptor tag {}", tag)))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Keypoint {
+    pub x: usize,
+    pub y: usize,
+    // Index of the image's kernel that best matches the pixels around (x, y).
+    pub kernel: usize,
+    // Euclidean distance to that kernel; smaller is a stronger match.
+    pub match_distance: u32,
+    pub descriptor: KeypointDescriptor
+}
+
+impl Keypoint {
+    // Squared distance between positions, plus the descriptor difference scaled by appearance_weight.
+    pub fn distance(&self, other: &Keypoint, appearance_weight: f64) -> f64 {
+        let spatial = self.x.abs_diff(other.x).pow(2) + self.y.abs_diff(other.y).pow(2);
+        spatial as f64 + appearance_weight * self.descriptor.difference(&other.descriptor)
+    }
+}
+
+impl Cacheable for Keypoint {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.x.write_to(output)?;
+        self.y.write_to(output)?;
+        self.kernel.write_to(output)?;
+        self.match_distance.write_to(output)?;
+        self.descriptor.write_to(output)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(Keypoint {x: usize::read_from(input)?, y: usize::read_from(input)?, kernel: usize::read_from(input)?,
+            match_distance: u32::read_from(input)?, descriptor: KeypointDescriptor::read_from(input)?})
+    }
+}
+
+// The keypoints of `kernel_points::find_keypoints`, in the same order, each with a descriptor.
+pub fn find_described_keypoints(img: &Image, num_kernels: usize, kernel_size: usize, num_keypoints: usize, kind: &DescriptorKind) -> Vec<Keypoint> {
+    let gradients = match kind {
+        DescriptorKind::GradientHistogram {..} => Some(gradients(img)),
+        _ => None
+    };
+    find_matched_keypoints(img, num_kernels, kernel_size, num_keypoints).iter()
+        .map(|(match_distance, x, y, kernel)| {
+            let descriptor = match kind {
+                DescriptorKind::Patch(side) => KeypointDescriptor::Patch(img.subimage(*x, *y, *side)),
+                DescriptorKind::Brief(brief) => {
+                    assert_eq!(brief.width(), brief.height());
+                    KeypointDescriptor::Brief(brief.apply_to(&img.subimage(*x, *y, brief.width())))
+                }
+                DescriptorKind::GradientHistogram {side, bins} => {
+                    let (x_gradients, y_gradients) = gradients.as_ref().unwrap();
+                    KeypointDescriptor::GradientHistogram(gradient_histogram(x_gradients, y_gradients, *x, *y, *side, *bins))
+                }
+            };
+            Keypoint {x: *x, y: *y, kernel: *kernel, match_distance: *match_distance, descriptor}
+        })
+        .collect()
+}
+
+// Bin 0 starts at angle -pi; pixels outside the image are skipped.
+fn gradient_histogram(x_gradients: &PixelImage<f32>, y_gradients: &PixelImage<f32>, x: usize, y: usize, side: usize, bins: usize) -> Vec<f32> {
+    let mut histogram = vec![0.0; bins];
+    ImageIterator::centered(x as isize, y as isize, side as isize, side as isize, 1)
+        .filter_map(|(x, y)| x_gradients.option_get(x, y).zip(y_gradients.option_get(x, y)))
+        .for_each(|(dx, dy)| {
+            let bin = ((dy.atan2(dx) + PI) / (2.0 * PI) * bins as f32) as usize % bins;
+            histogram[bin] += (dx * dx + dy * dy).sqrt();
+        });
+    let total: f32 = histogram.iter().sum();
+    if total > 0.0 {
+        histogram.iter_mut().for_each(|count| *count /= total);
+    }
+    histogram
+}
+
+// As `kernel_points::closest_for_all`: each keypoint of either group adds its distance to the
+// nearest keypoint of the other.
+pub fn closest_described_for_all(group1: &[Keypoint], group2: &[Keypoint], appearance_weight: f64) -> f64 {
+    closest_described_one_way(group1, group2, appearance_weight) + closest_described_one_way(group2, group1, appearance_weight)
+}
+
+pub fn closest_described_one_way(group1: &[Keypoint], group2: &[Keypoint], appearance_weight: f64) -> f64 {
+    group1.iter()
+        .map(|k1| group2.iter().map(|k2| k1.distance(k2, appearance_weight)).fold(f64::INFINITY, f64::min))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel_points::{find_keypoints, closest_for_all};
+
+    fn histogram_kind() -> DescriptorKind {
+        DescriptorKind::GradientHistogram {side: DESCRIPTOR_SIDE, bins: HISTOGRAM_BINS}
+    }
+
+    #[test]
+    fn test_same_points_as_find_keypoints() {
+        let img = Image::test_pattern(16, 12);
+        let points = find_keypoints(&img, 4, 3, 10);
+        for kind in [DescriptorKind::Patch(DESCRIPTOR_SIDE), DescriptorKind::Brief(Descriptor::equidistant(5, 5, 1, 2)), histogram_kind()] {
+            let keypoints = find_described_keypoints(&img, 4, 3, 10, &kind);
+            assert_eq!(points, keypoints.iter().map(|k| (k.x, k.y)).collect::<Vec<_>>());
+            assert!(keypoints.iter().all(|k| k.kernel < 4));
+            assert!(keypoints.windows(2).all(|pair| pair[0].match_distance <= pair[1].match_distance));
+            assert_eq!(0.0, closest_described_for_all(&keypoints, &keypoints, APPEARANCE_WEIGHT));
+            assert_eq!(closest_for_all(&points, &points) as f64, closest_described_for_all(&keypoints, &keypoints, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_differences() {
+        let dark = KeypointDescriptor::Patch(Image::from_slice(&[0; 4], 2, 2));
+        let light = KeypointDescriptor::Patch(Image::from_slice(&[255; 4], 2, 2));
+        assert_eq!(1.0, dark.difference(&light));
+        let flat = KeypointDescriptor::GradientHistogram(vec![0.0; 4]);
+        let right = KeypointDescriptor::GradientHistogram(vec![0.0, 0.0, 1.0, 0.0]);
+        let split = KeypointDescriptor::GradientHistogram(vec![0.0, 0.5, 0.5, 0.0]);
+        assert_eq!(1.0, flat.difference(&right));
+        assert_eq!(0.5, right.difference(&split));
+        let (p1, p2) = (Keypoint {x: 0, y: 0, kernel: 0, match_distance: 0, descriptor: right},
+                        Keypoint {x: 1, y: 2, kernel: 3, match_distance: 9, descriptor: split});
+        assert_eq!(5.0 + APPEARANCE_WEIGHT / 2.0, p1.distance(&p2, APPEARANCE_WEIGHT));
+    }
+
+    #[test]
+    fn test_gradient_histogram() {
+        // Intensity rising to the right has gradients pointing along +x, in the bin starting at 0.
+        let ramp = Image::from_slice(&(0..25).map(|i| (i % 5) as u8 * 40).collect::<Vec<_>>(), 5, 5);
+        let (x_gradients, y_gradients) = gradients(&ramp);
+        let histogram = gradient_histogram(&x_gradients, &y_gradients, 2, 2, 3, 4);
+        assert_eq!(vec![0.0, 0.0, 1.0, 0.0], histogram);
+    }
+
+    #[test]
+    fn test_cache() {
+        let img = Image::test_pattern(12, 12);
+        for kind in [DescriptorKind::Patch(3), DescriptorKind::Brief(Descriptor::equidistant(5, 5, 1, 2)), histogram_kind()] {
+            let keypoints = find_described_keypoints(&img, 4, 3, 6, &kind);
+            let mut bytes = Vec::new();
+            keypoints.write_to(&mut bytes).unwrap();
+            let restored = Vec::<Keypoint>::read_from(&mut bytes.as_slice()).unwrap();
+            assert_eq!(0.0, closest_described_for_all(&keypoints, &restored, APPEARANCE_WEIGHT));
+            assert_eq!(keypoints.iter().map(|k| (k.x, k.y, k.kernel, k.match_distance)).collect::<Vec<_>>(),
+                       restored.iter().map(|k| (k.x, k.y, k.kernel, k.match_distance)).collect::<Vec<_>>());
+        }
+        assert!(KeypointDescriptor::read_from(&mut [7u8].as_slice()).is_err());
+    }
+}
@@ -1,4 +1,26 @@
 pub mod mnist_data;
+pub mod pixel;
+pub mod image_io;
+pub mod image_folder;
+pub mod feature_cache;
+pub mod sampling;
+pub mod statistics;
+pub mod cross_validation;
+pub mod augmentation;
+pub mod perturbation;
+pub mod preprocessing;
+pub mod morphology;
+pub mod distance_transform;
+pub mod tangent_distance;
+pub mod idm;
+pub mod vector_distance;
+pub mod ssim;
+pub mod wasserstein;
+pub mod assignment;
+pub mod keypoint_descriptors;
+pub mod data_location;
+pub mod idx;
+pub mod datasets;
 pub mod euclidean_distance;
 pub mod permutation;
 pub mod brief;
@@ -9,4 +31,6 @@ pub mod kernel_patch;
 pub mod kernel_points;
 pub mod sobel;
 pub mod convolution_pyramid;
+#[cfg(test)]
+mod test_util;
 
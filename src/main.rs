@@ -1,16 +1,42 @@
 use std::io;
 use supervised_learning::Classifier;
-use distance_research::mnist_data::{Image, load_data_set};
+use distance_research::mnist_data::{Image, Grid};
+use distance_research::idx::LoadMode;
+use distance_research::datasets::Dataset;
+use distance_research::data_location::{resolve_data_dir, config_file_path, DATA_DIR_OPTION, DATA_DIR_ENV_VAR, CONFIG_KEY};
 use std::env;
+use std::process;
+use std::path::Path;
+use distance_research::image_io::{export_labeled, ImageFormat};
+use distance_research::image_folder::{load_image_folder, load_image_folder_with};
+use distance_research::sampling::Subsampling;
+use distance_research::statistics::{mean, standard_deviation};
+use distance_research::augmentation::AugmentationPipeline;
+use distance_research::preprocessing::{preprocessed, preprocessed_all, Preprocessing};
+use distance_research::perturbation::{default_curves, perturbed, Perturbation, RobustnessCurves};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use distance_research::cross_validation::{CrossValidation, CrossValidationResult};
+use distance_research::feature_cache::{Cacheable, CacheKey, FeatureCache, FeatureSets, CACHE_VERSION};
 use std::collections::{HashSet, BTreeMap, HashMap};
 use distance_research::brief::Descriptor;
-use distance_research::convolutional::{kernelize_all, kernelized_distance};
+use distance_research::convolutional::{kernelize_all, kernelized_distance, kernelize_all_f32, kernelized_distance_f32};
 use distance_research::kernel_patch::{kernelize_single_image, best_match_distance};
 use distance_research::patch::patchify;
 use distance_research::timing::print_time_milliseconds;
 use distance_research::kernel_points::{find_keypoints, closest_for_all};
-use distance_research::sobel::edge_image;
-use distance_research::convolution_pyramid::{kernel_stack_all, KernelPyramidImage, get_kernels_from};
+use distance_research::assignment::{assignment_distance, PointMetric};
+use distance_research::keypoint_descriptors::{find_described_keypoints, closest_described_for_all, DescriptorKind, DESCRIPTOR_SIDE, HISTOGRAM_BINS, APPEARANCE_WEIGHT};
+use distance_research::sobel::{edge_image, edge_magnitudes};
+use distance_research::morphology::skeleton;
+use distance_research::vector_distance::{manhattan_distance, chebyshev_distance, minkowski_distance, cosine_distance, pearson_distance,
+                                          chi_squared_distance, histogram_intersection_distance, canberra_distance};
+use distance_research::idm::{Idm, intensity_channels, gradient_channels};
+use distance_research::tangent_distance::{TangentImage, tangent_distance, one_sided_tangent_distance, TANGENT_SIGMA};
+use distance_research::distance_transform::{Shape, chamfer_distance, hausdorff_distance, directed_hausdorff_distance, modified_hausdorff_distance};
+use distance_research::wasserstein::{MassDistribution, SinkhornKernel, sinkhorn_distance, sliced_wasserstein_distance, SINKHORN_EPSILON, SINKHORN_ITERATIONS, NUM_SLICES};
+use distance_research::ssim::{ssim_dissimilarity, ms_ssim_dissimilarity, global_ssim_dissimilarity, SSIM_WINDOW, MS_SSIM_SCALES};
+use distance_research::convolution_pyramid::{kernel_stack_all, kernel_stack_all_with, KernelPyramidImage, get_kernels_from};
 
 const SHRINK_SEQUENCE: [usize; 5] = [50, 20, 10, 5, 2];
 
@@ -18,13 +44,29 @@ const SHRINK_FACTOR: usize = 50;
 const K: usize = 7;
 const PATCH_SIZE: usize = 3;
 const NUM_NEIGHBORS: usize = 8;
-const CLASSIC_BRIEF_PAIRS: usize = distance_research::mnist_data::IMAGE_DIMENSION * distance_research::mnist_data::IMAGE_DIMENSION * NUM_NEIGHBORS;
-const EQUIDISTANT_OFFSET: usize = distance_research::mnist_data::IMAGE_DIMENSION / 3;
 
 const HELP: &str = "help";
 const SHRINK: &str = "shrink";
 const PERMUTE: &str = "permute";
 const SEQ: &str = "sequence";
+const MMAP: &str = "mmap";
+const DATASET_OPTION: &str = "dataset=";
+const EXPORT_OPTION: &str = "export=";
+const IMAGE_DIR_OPTION: &str = "image_dir=";
+const SIDE_OPTION: &str = "side=";
+const CACHE_OPTION: &str = "cache=";
+const SUBSAMPLE_OPTION: &str = "subsample=";
+const SEEDS_OPTION: &str = "seeds=";
+const FOLDS_OPTION: &str = "folds=";
+const HOLDOUT_OPTION: &str = "holdout=";
+const STRATIFY: &str = "stratify";
+const HOLDOUT_FRACTION: f64 = 0.2;
+const AUGMENT_OPTION: &str = "augment=";
+const AUGMENT_SEED: u64 = 0;
+const PREPROCESS_OPTION: &str = "preprocess=";
+const ROBUSTNESS_OPTION: &str = "robustness=";
+const ROBUSTNESS_SEED: u64 = 0;
+const APPEARANCE_WEIGHT_OPTION: &str = "appearance_weight=";
 
 const BASELINE: &str = "baseline";
 const BRIEF: &str = "brief";
@@ -39,24 +81,91 @@ const EQUIDISTANT_BRIEF: &str = "equidistant";
 const EQUIDISTANT_3_3_BRIEF: &str = "equidistant_3_3";
 const COMPARE_KERNELS: &str = "compare_kernels";
 const COMPARE_KEYPOINTS: &str = "compare_keypoints";
+const KEYPOINT_ASSIGNMENT: &str = "keypoint_assignment";
+const KEYPOINT_ASSIGNMENT_L1: &str = "keypoint_assignment_l1";
+const UNMATCHED_DISTANCE: usize = 10;
+const KEYPOINT_PATCHES: &str = "keypoint_patches";
+const KEYPOINT_BRIEF: &str = "keypoint_brief";
+const KEYPOINT_GRADIENTS: &str = "keypoint_gradients";
 const SOBEL_DIST: &str = "edge_distance";
+const SOBEL_DIST_U16: &str = "edge_distance_u16";
+const SKELETON_DIST: &str = "skeleton_distance";
+const CHAMFER: &str = "chamfer";
+const HAUSDORFF: &str = "hausdorff";
+const DIRECTED_HAUSDORFF: &str = "directed_hausdorff";
+const MODIFIED_HAUSDORFF: &str = "modified_hausdorff";
+const CHAMFER_INK: &str = "chamfer_ink";
+const HAUSDORFF_INK: &str = "hausdorff_ink";
+const DIRECTED_HAUSDORFF_INK: &str = "directed_hausdorff_ink";
+const MODIFIED_HAUSDORFF_INK: &str = "modified_hausdorff_ink";
+const TANGENT: &str = "tangent";
+const TANGENT_ONE_SIDED: &str = "tangent_one_sided";
+const IDM: &str = "idm";
+const IDM_SOBEL: &str = "idm_sobel";
+const SHIFT_EUCLIDEAN: &str = "shift_euclidean";
+const SHIFT_RADIUS: usize = 2;
+const MANHATTAN: &str = "manhattan";
+const CHEBYSHEV: &str = "chebyshev";
+const MINKOWSKI: &str = "minkowski";
+const MINKOWSKI_P: f64 = 3.0;
+const COSINE: &str = "cosine";
+const PEARSON: &str = "pearson";
+const CHI_SQUARED: &str = "chi_squared";
+const HISTOGRAM_INTERSECTION: &str = "histogram_intersection";
+const CANBERRA: &str = "canberra";
+const SSIM: &str = "ssim";
+const MS_SSIM: &str = "ms_ssim";
+const CONVOLUTIONAL_PYRAMID_SSIM: &str = "convolutional_pyramid_ssim";
+const SINKHORN: &str = "sinkhorn";
+const SLICED_WASSERSTEIN: &str = "sliced_wasserstein";
+const CONVOLUTIONAL_1_F32: &str = "convolutional1_f32";
 
-fn main() -> io::Result<()> {
+fn main() {
     let args: HashSet<String> = env::args().collect();
     if args.contains(HELP) {
         help_message();
-    } else {
-        train_and_test(&args)?;
+    } else if let Err(e) = train_and_test(&args) {
+        // Display rather than Debug, so that data location errors list the paths tried legibly.
+        eprintln!("Error: {}", e);
+        process::exit(1);
     }
-    Ok(())
 }
 
 fn help_message() {
     println!("Usage: flairs33 [options]:");
     println!("\t{}: print this message", HELP);
     println!("\t{}: runs additional experiment that permutes image pixels", PERMUTE);
+    println!("\t{}<n>: runs additional experiment training on n times as many images, adding randomly shifted,", AUGMENT_OPTION);
+    println!("\t\trotated, scaled, sheared, and elastically distorted copies of each training image");
+    println!("\t{}<steps>: normalize every image before conversion, applying comma-separated steps in order from {}", PREPROCESS_OPTION,
+             Preprocessing::all().iter().map(|p| p.name()).collect::<Vec<_>>().join(", "));
+    println!("\t{}<dir>: rerun each variant on testing images perturbed at increasing severity (shift, rotation, noise,", ROBUSTNESS_OPTION);
+    println!("\t\tocclusion, contrast, brightness), writing <dir>/<variant>.csv of error rate by severity;");
+    println!("\t\tnot available with {}, {}, {}, or {}", SEQ, SEEDS_OPTION, FOLDS_OPTION, HOLDOUT_OPTION);
+    println!("\t{}<w>: squared pixel distance that a complete appearance mismatch costs in {}, {}, and {} (default {})", APPEARANCE_WEIGHT_OPTION,
+             KEYPOINT_PATCHES, KEYPOINT_BRIEF, KEYPOINT_GRADIENTS, APPEARANCE_WEIGHT);
     println!("\t{}: Use only 1 out of {} training/testing images", SHRINK, SHRINK_FACTOR);
     println!("\t{}: Use 1/50, 1/20, 1/10, 1/5, and 1/2 training/testing images", SEQ);
+    println!("\t{}<mode>: how {} and {} pick images; one of {} (default {}, or {} with {})", SUBSAMPLE_OPTION, SHRINK, SEQ,
+             Subsampling::all().iter().map(|s| s.name()).collect::<Vec<_>>().join(", "), Subsampling::Modulo.name(), Subsampling::Random.name(), SEEDS_OPTION);
+    println!("\t{}<n>: run {} n times with seeds 0 to n-1, then report the mean and standard deviation of each error rate", SEEDS_OPTION, SEQ);
+    println!("\t{}<k>: score each variant by k-fold cross-validation on the training images; the testing images are not used", FOLDS_OPTION);
+    println!("\t{}<n>: as {}, but with n random holdouts of {}% of the training images", HOLDOUT_OPTION, FOLDS_OPTION, HOLDOUT_FRACTION * 100.0);
+    println!("\t{}: with {} or {}, keep each class's share of the images in every fold", STRATIFY, FOLDS_OPTION, HOLDOUT_OPTION);
+    println!("\t{}<path>: directory holding the IDX files", DATA_DIR_OPTION);
+    println!("\t\tIf absent, the {} environment variable is used, then a \"{} = <path>\" line in {}", DATA_DIR_ENV_VAR, CONFIG_KEY, config_file_path().display());
+    println!("\t{}: memory-map the IDX files when loading them", MMAP);
+    println!("\t{}<name>: dataset to use (default {}); one of {}", DATASET_OPTION, Dataset::Mnist.name(),
+             Dataset::all().iter().map(|d| d.name()).collect::<Vec<_>>().join(", "));
+    println!("\t\tMNIST files go directly in the data directory; the others go in a subdirectory:");
+    for dataset in Dataset::all().iter().filter(|d| !d.subdirectory().is_empty()) {
+        println!("\t\t\t{}: {}", dataset.name(), dataset.subdirectory());
+    }
+    println!("\t{}<dir>: load images from <dir>/train/<class>/ and <dir>/test/<class>/ (.png or .pgm) instead of a dataset", IMAGE_DIR_OPTION);
+    println!("\t\tClass directories named 0-255 use those numbers as labels; otherwise classes are numbered alphabetically");
+    println!("\t{}<n>: with {}, resize each square image to n x n by a whole-number factor", SIDE_OPTION, IMAGE_DIR_OPTION);
+    println!("\t{}<dir>: keep converted features in <dir> (format version {}), so later runs can skip conversion", CACHE_OPTION, CACHE_VERSION);
+    println!("\t{}<dir>: write the training and testing images as PNG files to <dir>/train/<label>/ and <dir>/test/<label>/", EXPORT_OPTION);
     println!("\nAlgorithmic options:");
     println!("The eight variants of the paper are given in order of appearance in Tables 1 and 2.");
     println!("All variants describe a knn (k=7) distance function variation:");
@@ -74,47 +183,243 @@ fn help_message() {
     println!("\t{}: Equidistant 3x3 kernel BRIEF, comparing 3x3 neighborhoods around the pixel pairs", EQUIDISTANT_3_3_BRIEF);
     println!("\t{}: Find 8 3x3 kernels for each image; add distance from each kernel to its best match", COMPARE_KERNELS);
     println!("\t{}: Find 8 3x3 kernels for each image; find 16 (x,y) points that best mach any of them; add distance from each point to its best match", COMPARE_KEYPOINTS);
+    println!("\t{}: {} points, matched one-to-one to minimize total squared distance; each unmatched point costs {} pixels", KEYPOINT_ASSIGNMENT, COMPARE_KEYPOINTS, UNMATCHED_DISTANCE);
+    println!("\t{}: {}, but with Manhattan distance between points", KEYPOINT_ASSIGNMENT_L1, KEYPOINT_ASSIGNMENT);
+    println!("\t{}: {} points, each also comparing the {}x{} patch around it", KEYPOINT_PATCHES, COMPARE_KEYPOINTS, DESCRIPTOR_SIDE, DESCRIPTOR_SIDE);
+    println!("\t{}: {}, comparing equidistant BRIEF bits of each patch", KEYPOINT_BRIEF, KEYPOINT_PATCHES);
+    println!("\t{}: {}, comparing {}-bin histograms of Sobel gradient orientations in each patch", KEYPOINT_GRADIENTS, KEYPOINT_PATCHES, HISTOGRAM_BINS);
     println!("\t{}: Euclidean distance between Sobel edge images", SOBEL_DIST);
+    println!("\t{}: {}, but with unclipped u16 edge magnitudes", SOBEL_DIST_U16, SOBEL_DIST);
+    println!("\t{}: Euclidean distance between Zhang-Suen skeletons of Otsu-thresholded images", SKELETON_DIST);
+    println!("\t{}: symmetric chamfer distance (sum of mean nearest-point distances) between Otsu-thresholded Sobel edges", CHAMFER);
+    println!("\t{}: Hausdorff distance between Otsu-thresholded Sobel edges", HAUSDORFF);
+    println!("\t{}: one-way Hausdorff distance (farthest of one image's edge points from the other's), which is not symmetric", DIRECTED_HAUSDORFF);
+    println!("\t{}: modified (mean-based) Hausdorff distance between Otsu-thresholded Sobel edges", MODIFIED_HAUSDORFF);
+    println!("\t{}, {}, {}, {}: as above, between Otsu-thresholded images rather than their edges",
+             CHAMFER_INK, HAUSDORFF_INK, DIRECTED_HAUSDORFF_INK, MODIFIED_HAUSDORFF_INK);
+    println!("\t{}: two-sided tangent distance over translation, rotation, scaling, shear, axis deformation, and thickness", TANGENT);
+    println!("\t{}: {}, but using only the tangents of one image of each pair", TANGENT_ONE_SIDED, TANGENT);
+    let idm = Idm::new();
+    println!("\t{}: image distortion model, matching {}x{} pixel contexts within {} pixels of each pixel", IDM, 2 * idm.context + 1, 2 * idm.context + 1, idm.warp);
+    println!("\t{}: {}, on signed Sobel gradients", IDM_SOBEL, IDM);
+    println!("\t{}: smallest Euclidean distance over all shifts of up to {} pixels in each direction", SHIFT_EUCLIDEAN, SHIFT_RADIUS);
+    println!("\t{}, {}, {}: L1, L-infinity, and L{} distances between pixel values", MANHATTAN, CHEBYSHEV, MINKOWSKI, MINKOWSKI_P);
+    println!("\t{}, {}: 1 - cosine similarity and 1 - Pearson correlation of pixel values", COSINE, PEARSON);
+    println!("\t{}, {}, {}: distances between images treated as histograms of intensity", CHI_SQUARED, HISTOGRAM_INTERSECTION, CANBERRA);
+    println!("\t{}: 1 - mean SSIM over {}x{} windows", SSIM, SSIM_WINDOW, SSIM_WINDOW);
+    println!("\t{}: 1 - multi-scale SSIM over {} scales", MS_SSIM, MS_SSIM_SCALES);
+    println!("\t{}: {}, matching kernels to the original images by 1 - SSIM instead of Euclidean distance", CONVOLUTIONAL_PYRAMID_SSIM, CONVOLUTIONAL_PYRAMID);
+    println!("\t{}: earth mover's distance between images as mass distributions, by {} Sinkhorn iterations with epsilon {}", SINKHORN, SINKHORN_ITERATIONS, SINKHORN_EPSILON);
+    println!("\t\tQuadratic in inked pixels per comparison, so meant only for subsampled data ({} or {})", SHRINK, SEQ);
+    println!("\t{}: earth mover's distance averaged over projections onto {} directions", SLICED_WASSERSTEIN, NUM_SLICES);
+    println!("\t{}: {}, but with unclipped f32 kernel distances", CONVOLUTIONAL_1_F32, CONVOLUTIONAL_1);
+}
+
+fn num_seeds(args: &HashSet<String>) -> io::Result<Option<u64>> {
+    match args.iter().find_map(|arg| arg.strip_prefix(SEEDS_OPTION)) {
+        None => Ok(None),
+        Some(seeds) => match seeds.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Bad seed count \"{}\"; try {}", seeds, HELP)))
+        }
+    }
+}
+
+// Random by default when several seeds are requested, since modulo subsampling ignores the seed.
+fn selected_subsampling(args: &HashSet<String>, seeds: Option<u64>) -> io::Result<Subsampling> {
+    match args.iter().find_map(|arg| arg.strip_prefix(SUBSAMPLE_OPTION)) {
+        None => Ok(if seeds.is_some() {Subsampling::Random} else {Subsampling::Modulo}),
+        Some(name) => Subsampling::from_name(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown subsampling \"{}\"; try {}", name, HELP)))
+    }
+}
+
+fn selected_validation(args: &HashSet<String>) -> io::Result<Option<CrossValidation>> {
+    let count = |option: &str| -> io::Result<Option<usize>> {
+        match args.iter().find_map(|arg| arg.strip_prefix(option)) {
+            None => Ok(None),
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n >= 1 => Ok(Some(n)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Bad count \"{}\" for {}; try {}", n, option, HELP)))
+            }
+        }
+    };
+    let validation = match (count(FOLDS_OPTION)?, count(HOLDOUT_OPTION)?) {
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Choose only one of {} and {}", FOLDS_OPTION, HOLDOUT_OPTION))),
+        (Some(1), None) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} needs at least 2 folds", FOLDS_OPTION))),
+        (Some(folds), None) => CrossValidation::k_fold(folds),
+        (None, Some(repetitions)) => CrossValidation::repeated_holdout(repetitions, HOLDOUT_FRACTION)
+    };
+    Ok(Some(if args.contains(STRATIFY) {validation.stratified()} else {validation}))
+}
+
+// Checked before any images load. Cross-validation never uses the testing images that robustness
+// runs perturb, and each run of a sequence would overwrite the curves of the one before.
+fn robustness_dir(args: &HashSet<String>) -> io::Result<Option<&str>> {
+    let dir = args.iter().find_map(|arg| arg.strip_prefix(ROBUSTNESS_OPTION));
+    let conflicts = [FOLDS_OPTION, HOLDOUT_OPTION, SEEDS_OPTION].iter().any(|option| args.iter().any(|arg| arg.starts_with(option))) || args.contains(SEQ);
+    if dir.is_some() && conflicts {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} cannot be combined with {}, {}, {}, or {}", ROBUSTNESS_OPTION, FOLDS_OPTION, HOLDOUT_OPTION, SEQ, SEEDS_OPTION)))
+    } else {
+        Ok(dir)
+    }
+}
+
+fn selected_preprocessing(args: &HashSet<String>) -> io::Result<Vec<Preprocessing>> {
+    match args.iter().find_map(|arg| arg.strip_prefix(PREPROCESS_OPTION)) {
+        None => Ok(Vec::new()),
+        Some(steps) => steps.split(',')
+            .map(|name| Preprocessing::from_name(name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown preprocessing step \"{}\"; try {}", name, HELP))))
+            .collect()
+    }
+}
+
+fn appearance_weight(args: &HashSet<String>) -> io::Result<f64> {
+    match args.iter().find_map(|arg| arg.strip_prefix(APPEARANCE_WEIGHT_OPTION)) {
+        None => Ok(APPEARANCE_WEIGHT),
+        Some(weight) => match weight.parse::<f64>() {
+            Ok(w) if w >= 0.0 => Ok(w),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Bad appearance weight \"{}\"; try {}", weight, HELP)))
+        }
+    }
+}
+
+fn augmentation_factor(args: &HashSet<String>) -> io::Result<Option<usize>> {
+    match args.iter().find_map(|arg| arg.strip_prefix(AUGMENT_OPTION)) {
+        None => Ok(None),
+        Some(factor) => match factor.parse::<usize>() {
+            Ok(n) if n >= 2 => Ok(Some(n)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Bad augmentation factor \"{}\"; it must be at least 2", factor)))
+        }
+    }
+}
+
+fn selected_dataset(args: &HashSet<String>) -> io::Result<Dataset> {
+    match args.iter().find_map(|arg| arg.strip_prefix(DATASET_OPTION)) {
+        None => Ok(Dataset::Mnist),
+        Some(name) => Dataset::from_name(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown dataset \"{}\"; try {}", name, HELP)))
+    }
+}
+
+// Labeled training and testing images.
+type TrainingAndTesting = (Vec<(u8,Image)>, Vec<(u8,Image)>);
+
+fn load_dataset(args: &HashSet<String>) -> io::Result<TrainingAndTesting> {
+    let dataset = selected_dataset(args)?;
+    println!("Dataset: {} ({} classes)", dataset.name(), dataset.num_classes());
+    let data_dir = resolve_data_dir(args, &dataset.required_files())?;
+    let load_mode = if args.contains(MMAP) {LoadMode::MemoryMapped} else {LoadMode::Buffered};
+    Ok((dataset.load_training(&data_dir, load_mode)?, dataset.load_testing(&data_dir, load_mode)?))
+}
+
+fn load_image_dir(args: &HashSet<String>, root: &Path) -> io::Result<TrainingAndTesting> {
+    let side = match args.iter().find_map(|arg| arg.strip_prefix(SIDE_OPTION)) {
+        None => None,
+        Some(side) => Some(side.parse::<usize>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Bad side \"{}\"; try {}", side, HELP)))?)
+    };
+    let training = load_image_folder(&root.join("train"), side)?;
+    let testing = load_image_folder_with(&root.join("test"), &training.class_names, side)?;
+    let names: Vec<&str> = training.class_names.values().map(|name| name.as_str()).collect();
+    println!("Image directory: {} ({} classes: {})", root.display(), training.num_classes(), names.join(", "));
+    Ok((training.images, testing.images))
 }
 
 fn train_and_test(args: &HashSet<String>) -> io::Result<()> {
-    let mut training_images = load_data_set("train")?;
-    let mut testing_images = load_data_set("t10k")?;
+    // Follow-up experiments are parsed before any images load, so a bad value fails fast.
+    let augmentation = augmentation_factor(args)?;
+    let robustness = robustness_dir(args)?;
+    let image_dir = args.iter().find_map(|arg| arg.strip_prefix(IMAGE_DIR_OPTION));
+    let (mut training_images, mut testing_images) = match image_dir {
+        Some(root) => load_image_dir(args, Path::new(root))?,
+        None => load_dataset(args)?
+    };
+    // Names the data in cache keys; the keys also fingerprint the images themselves.
+    let source = match image_dir {
+        Some(root) => root.to_string(),
+        None => selected_dataset(args)?.name()
+    };
 
+    if let Some(export_dir) = args.iter().find_map(|arg| arg.strip_prefix(EXPORT_OPTION)) {
+        let export_dir = Path::new(export_dir);
+        print_time_milliseconds(&format!("exporting images to {}", export_dir.display()), || -> io::Result<()> {
+            export_labeled(&training_images, export_dir.join("train"), ImageFormat::Png, 1)?;
+            export_labeled(&testing_images, export_dir.join("test"), ImageFormat::Png, 1)
+        })?;
+    }
+
+    let seeds = num_seeds(args)?;
+    let subsampling = selected_subsampling(args, seeds)?;
     if args.contains(SEQ) {
-        for shrink in SHRINK_SEQUENCE.iter() {
-            println!("Shrinking by {}", shrink);
-            run_experiments(args, distance_research::mnist_data::discard(&training_images, *shrink),
-                            distance_research::mnist_data::discard(&testing_images, *shrink))?;
+        let mut errors: BTreeMap<(usize,String),Vec<f64>> = BTreeMap::new();
+        for seed in 0..seeds.unwrap_or(1) {
+            for shrink in SHRINK_SEQUENCE.iter() {
+                println!("Shrinking by {} ({} subsampling, seed {})", shrink, subsampling.name(), seed);
+                let run_errors = run_experiments(args, &source, subsampling.apply(&training_images, *shrink, seed),
+                                                 subsampling.apply(&testing_images, *shrink, seed), augmentation, None)?;
+                for (label, error) in run_errors {
+                    errors.entry((*shrink, label)).or_default().push(error);
+                }
+            }
+        }
+        if seeds.is_some() {
+            print_error_spread(&errors);
         }
 
     } else {
         if args.contains(SHRINK) {
-            println!("Shrinking by {}", SHRINK_FACTOR);
-            training_images = distance_research::mnist_data::discard(&training_images, SHRINK_FACTOR);
-            testing_images = distance_research::mnist_data::discard(&testing_images, SHRINK_FACTOR);
+            println!("Shrinking by {} ({} subsampling)", SHRINK_FACTOR, subsampling.name());
+            training_images = subsampling.apply(&training_images, SHRINK_FACTOR, 0);
+            testing_images = subsampling.apply(&testing_images, SHRINK_FACTOR, 0);
         }
 
-        run_experiments(args, training_images, testing_images)?;
+        run_experiments(args, &source, training_images, testing_images, augmentation, robustness)?;
     }
 
     Ok(())
 }
 
-fn run_experiments(args: &HashSet<String>, training_images: Vec<(u8,Image)>, testing_images: Vec<(u8,Image)>) -> io::Result<()> {
+fn print_error_spread(errors: &BTreeMap<(usize,String),Vec<f64>>) {
+    println!("Error rates over seeds (mean, standard deviation)");
+    for ((shrink, label), rates) in errors.iter().rev() {
+        println!("1/{} {}: {:.2}% ± {:.2}% ({} runs)", shrink, label, mean(rates), standard_deviation(rates), rates.len());
+    }
+}
+
+// Returns the error rate of each variant run on the unpermuted images. With an augmentation
+// factor, also reruns them on augmented training images; with robustness_dir, also writes
+// robustness curves there.
+fn run_experiments(args: &HashSet<String>, source: &str, training_images: Vec<(u8,Image)>, testing_images: Vec<(u8,Image)>,
+                   augmentation: Option<usize>, robustness_dir: Option<&str>) -> io::Result<BTreeMap<String,f64>> {
+    // Descriptors and the Sinkhorn kernel are sized from the first training image.
+    if training_images.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No training images to run experiments on in {}", source)));
+    }
     let mut data = ExperimentData {
         training: training_images,
         testing: testing_images,
         descriptors: Default::default(),
-        errors: BTreeMap::new()
+        errors: BTreeMap::new(),
+        source: source.to_string(),
+        cache: args.iter().find_map(|arg| arg.strip_prefix(CACHE_OPTION)).map(FeatureCache::new),
+        validation: selected_validation(args)?,
+        preprocessing: selected_preprocessing(args)?,
+        appearance_weight: appearance_weight(args)?
     };
 
-    data.add_descriptor(BRIEF, distance_research::brief::Descriptor::classic_gaussian_brief(CLASSIC_BRIEF_PAIRS, distance_research::mnist_data::IMAGE_DIMENSION, distance_research::mnist_data::IMAGE_DIMENSION));
-    data.add_descriptor(UNIFORM_BRIEF, distance_research::brief::Descriptor::classic_uniform_brief(CLASSIC_BRIEF_PAIRS, distance_research::mnist_data::IMAGE_DIMENSION, distance_research::mnist_data::IMAGE_DIMENSION));
-    data.add_descriptor(UNIFORM_NEIGHBORS, distance_research::brief::Descriptor::uniform_neighbor(NUM_NEIGHBORS, distance_research::mnist_data::IMAGE_DIMENSION, distance_research::mnist_data::IMAGE_DIMENSION));
-    data.add_descriptor(GAUSSIAN_NEIGHBORS, distance_research::brief::Descriptor::gaussian_neighbor(NUM_NEIGHBORS, distance_research::mnist_data::IMAGE_DIMENSION / 3, distance_research::mnist_data::IMAGE_DIMENSION, distance_research::mnist_data::IMAGE_DIMENSION));
-    data.add_descriptor(GAUSSIAN_7, distance_research::brief::Descriptor::gaussian_neighbor(NUM_NEIGHBORS, distance_research::mnist_data::IMAGE_DIMENSION / 7, distance_research::mnist_data::IMAGE_DIMENSION, distance_research::mnist_data::IMAGE_DIMENSION));
-    data.add_descriptor(EQUIDISTANT_BRIEF, distance_research::brief::Descriptor::equidistant(distance_research::mnist_data::IMAGE_DIMENSION, distance_research::mnist_data::IMAGE_DIMENSION, EQUIDISTANT_OFFSET, EQUIDISTANT_OFFSET));
+    // Descriptors are shaped to match the images, which need not be square or 28x28.
+    let (width, height) = (data.training[0].1.width(), data.training[0].1.height());
+    let classic_brief_pairs = width * height * NUM_NEIGHBORS;
+    let stdev_base = width.min(height);
+    data.add_descriptor(BRIEF, distance_research::brief::Descriptor::classic_gaussian_brief(classic_brief_pairs, width, height));
+    data.add_descriptor(UNIFORM_BRIEF, distance_research::brief::Descriptor::classic_uniform_brief(classic_brief_pairs, width, height));
+    data.add_descriptor(UNIFORM_NEIGHBORS, distance_research::brief::Descriptor::uniform_neighbor(NUM_NEIGHBORS, width, height));
+    data.add_descriptor(GAUSSIAN_NEIGHBORS, distance_research::brief::Descriptor::gaussian_neighbor(NUM_NEIGHBORS, stdev_base / 3, width, height));
+    data.add_descriptor(GAUSSIAN_7, distance_research::brief::Descriptor::gaussian_neighbor(NUM_NEIGHBORS, stdev_base / 7, width, height));
+    data.add_descriptor(EQUIDISTANT_BRIEF, distance_research::brief::Descriptor::equidistant(width, height, width / 3, height / 3));
 
     data.run_all_tests_with(&args);
 
@@ -128,9 +433,38 @@ fn run_experiments(args: &HashSet<String>, training_images: Vec<(u8,Image)>, tes
         println!();
     }
 
+    if let Some(factor) = augmentation {
+        let pipeline = AugmentationPipeline::new(factor, AUGMENT_SEED);
+        let mut augmented_data = print_time_milliseconds(&format!("augmenting training images {}x", factor), || data.augmented(&pipeline));
+        augmented_data.run_all_tests_with(args);
+        println!("Augmented results");
+        augmented_data.print_errors();
+        println!();
+    }
+
+    if let Some(dir) = robustness_dir {
+        let mut curves = RobustnessCurves::new();
+        for curve in default_curves() {
+            // Severity 0 is the unperturbed testing set.
+            if let Some(first) = curve.first() {
+                curves.add(first.name(), 0.0, &data.errors);
+            }
+            for perturbation in curve.iter() {
+                println!("Perturbing testing images: {} {}", perturbation.name(), perturbation.severity());
+                let mut perturbed_data = data.perturbed(perturbation, ROBUSTNESS_SEED);
+                perturbed_data.run_all_tests_with(args);
+                curves.add(perturbation.name(), perturbation.severity(), &perturbed_data.errors);
+            }
+        }
+        for path in curves.write_csv(dir)? {
+            println!("Wrote {}", path.display());
+        }
+        println!();
+    }
+
     println!("Original results");
     data.print_errors();
-    Ok(())
+    Ok(data.errors)
 }
 
 fn permuted_data_set(permutation: &Vec<usize>, data: &Vec<(u8,Image)>) -> Vec<(u8,Image)> {
@@ -148,22 +482,46 @@ pub struct ExperimentData {
     training: Vec<(u8,Image)>,
     testing: Vec<(u8,Image)>,
     descriptors: HashMap<String,Descriptor>,
-    errors: BTreeMap<String,f64>
+    errors: BTreeMap<String,f64>,
+    source: String,
+    cache: Option<FeatureCache>,
+    // When present, variants are scored by cross-validation on the training images alone.
+    validation: Option<CrossValidation>,
+    // Applied to each image just before a variant converts it, so derived experiments (permuted,
+    // augmented, perturbed) normalize their own images.
+    preprocessing: Vec<Preprocessing>,
+    appearance_weight: f64
 }
 
 impl ExperimentData {
-    pub fn build_and_test_model<I: Clone, M: Copy + PartialEq + PartialOrd, C: Fn(&Image) -> I, D: Fn(&I,&I) -> M>
-    (&mut self, label: &str, conversion: C, distance: D) {
-        self.build_and_test_converting_all(label, |v| convert_all(v, &conversion), distance);
+    // parameters describes any conversion settings not implied by label; it is part of the cache key.
+    pub fn build_and_test_model<I: Clone + Cacheable, M: Copy + PartialEq + PartialOrd, C: Fn(&Image) -> I, D: Fn(&I,&I) -> M>
+    (&mut self, label: &str, parameters: &str, conversion: C, distance: D) {
+        if let Some(validation) = self.validation {
+            let steps = &self.preprocessing;
+            let outcome = print_time_milliseconds(&format!("cross-validating {} ({})", label, validation),
+                                                  || validation.evaluate_model(&self.training, K, |img| conversion(&preprocessed(img, steps)), &distance));
+            self.record_validation(label, outcome);
+            return;
+        }
+        self.build_and_test_converting_all(label, parameters, |v| convert_all(v, &conversion), distance);
     }
 
-    pub fn build_and_test_converting_all<I: Clone, M: Copy + PartialEq + PartialOrd, C: Fn(&Vec<(u8,Image)>) -> Vec<(u8,I)>, D: Fn(&I,&I) -> M>
-    (&mut self, label: &str, conversion: C, distance: D) {
-        let training_images = print_time_milliseconds(&format!("converting training images to {}", label),
-                                                      || conversion(&self.training));
-
-        let testing_images = print_time_milliseconds(&format!("converting testing images to {}", label),
-                                                     || conversion(&self.testing));
+    pub fn build_and_test_converting_all<I: Clone + Cacheable, M: Copy + PartialEq + PartialOrd, C: Fn(&Vec<(u8,Image)>) -> Vec<(u8,I)>, D: Fn(&I,&I) -> M>
+    (&mut self, label: &str, parameters: &str, conversion: C, distance: D) {
+        let steps = self.preprocessing.clone();
+        let conversion = |images: &Vec<(u8,Image)>| if steps.is_empty() {conversion(images)} else {conversion(&preprocessed_all(images, &steps))};
+        if let Some(validation) = self.validation {
+            let outcome = print_time_milliseconds(&format!("cross-validating {} ({})", label, validation),
+                                                  || validation.evaluate_converting_all(&self.training, K, conversion, &distance));
+            self.record_validation(label, outcome);
+            return;
+        }
+        let parameters = match steps.iter().map(|p| p.name()).collect::<Vec<_>>().join(",") {
+            names if names.is_empty() => parameters.to_string(),
+            names => format!("{} preprocess={}", parameters, names).trim().to_string()
+        };
+        let (training_images, testing_images) = self.converted(label, &parameters, conversion);
 
         let mut model = knn::Knn::new(K, distance);
         print_time_milliseconds(&format!("training {} model (k={})", label, K),
@@ -175,6 +533,38 @@ impl ExperimentData {
         self.errors.insert(label.to_string(), error_percentage);
     }
 
+    fn record_validation(&mut self, label: &str, outcome: CrossValidationResult) {
+        println!("{}", outcome);
+        self.errors.insert(label.to_string(), outcome.mean());
+    }
+
+    fn converted<I: Cacheable, C: Fn(&Vec<(u8,Image)>) -> Vec<(u8,I)>>(&self, label: &str, parameters: &str, conversion: C) -> FeatureSets<I> {
+        let cache = match &self.cache {
+            None => return self.convert(label, conversion),
+            Some(cache) => cache
+        };
+        let key = CacheKey::new(&self.source, label, parameters, &self.training, &self.testing);
+        match print_time_milliseconds(&format!("loading cached {} features", label), || cache.load(&key)) {
+            Ok(Some(features)) => return features,
+            Ok(None) => println!("No cached {} features at {}", label, cache.path_for(&key).display()),
+            Err(e) => println!("Ignoring unreadable cache file {}: {}", cache.path_for(&key).display(), e)
+        }
+        let (training_images, testing_images) = self.convert(label, conversion);
+        if let Err(e) = cache.store(&key, &training_images, &testing_images) {
+            println!("Could not cache {} features: {}", label, e);
+        }
+        (training_images, testing_images)
+    }
+
+    fn convert<I, C: Fn(&Vec<(u8,Image)>) -> Vec<(u8,I)>>(&self, label: &str, conversion: C) -> FeatureSets<I> {
+        let training_images = print_time_milliseconds(&format!("converting training images to {}", label),
+                                                      || conversion(&self.training));
+
+        let testing_images = print_time_milliseconds(&format!("converting testing images to {}", label),
+                                                     || conversion(&self.testing));
+        (training_images, testing_images)
+    }
+
     pub fn get_descriptor(&self, name: &str) -> Descriptor {
         match self.descriptors.get(name) {
             Some(d) => d.clone(),
@@ -190,7 +580,7 @@ impl ExperimentData {
 
     pub fn run_all_tests_with(&mut self, args: &HashSet<String>) {
         if args.contains(BASELINE) {
-            self.build_and_test_model(BASELINE, |v| v.clone(), distance_research::euclidean_distance::euclidean_distance);
+            self.build_and_test_model(BASELINE, "", |v| v.clone(), distance_research::euclidean_distance::euclidean_distance);
         }
         if args.contains(BRIEF) {
             self.build_and_test_descriptor(BRIEF);
@@ -212,47 +602,183 @@ impl ExperimentData {
         }
         if args.contains(EQUIDISTANT_3_3_BRIEF) {
             let descriptor = self.get_descriptor(EQUIDISTANT_BRIEF);
-            self.build_and_test_model(EQUIDISTANT_3_3_BRIEF, |img| descriptor.apply_kernel(img, 3), bits::distance);
+            self.build_and_test_model(EQUIDISTANT_3_3_BRIEF, "", |img| descriptor.apply_kernel(img, 3), bits::distance);
         }
         if args.contains(PATCH) {
             self.build_and_test_patch(PATCH, PATCH_SIZE);
         }
         if args.contains(CONVOLUTIONAL_1) {
-            self.build_and_test_converting_all(CONVOLUTIONAL_1, |images| kernelize_all(images, 1), kernelized_distance);
+            self.build_and_test_converting_all(CONVOLUTIONAL_1, "levels=1", |images| kernelize_all(images, 1), kernelized_distance);
         }
         if args.contains(CONVOLUTIONAL_PYRAMID) {
-            let kernels = get_kernels_from(&self.training, 8);
-            self.build_and_test_converting_all(CONVOLUTIONAL_PYRAMID, |images| kernel_stack_all(images, &kernels, 2), KernelPyramidImage::distance);
+            // Kernels come from the images as they will be converted, preprocessing included.
+            let kernels = get_kernels_from(&preprocessed_all(&self.training, &self.preprocessing), 8);
+            self.build_and_test_converting_all(CONVOLUTIONAL_PYRAMID, "kernels=8 levels=2", |images| kernel_stack_all(images, &kernels, 2), KernelPyramidImage::distance);
+        }
+        if args.contains(CONVOLUTIONAL_PYRAMID_SSIM) {
+            let kernels = get_kernels_from(&preprocessed_all(&self.training, &self.preprocessing), 8);
+            self.build_and_test_converting_all(CONVOLUTIONAL_PYRAMID_SSIM, "kernels=8 levels=2", |images| kernel_stack_all_with(images, &kernels, 2, &global_ssim_dissimilarity), KernelPyramidImage::distance);
+        }
+        if args.contains(CONVOLUTIONAL_1_F32) {
+            self.build_and_test_converting_all(CONVOLUTIONAL_1_F32, "levels=1", |images| kernelize_all_f32(images, 1), kernelized_distance_f32);
         }
         if args.contains(SOBEL_DIST) {
-            self.build_and_test_converting_all(SOBEL_DIST, |images| images.iter().map(|(label, img)| (*label, edge_image(img))).collect(), distance_research::euclidean_distance::euclidean_distance);
+            self.build_and_test_converting_all(SOBEL_DIST, "", |images| images.iter().map(|(label, img)| (*label, edge_image(img))).collect(), distance_research::euclidean_distance::euclidean_distance);
+        }
+        if args.contains(SOBEL_DIST_U16) {
+            self.build_and_test_model(SOBEL_DIST_U16, "", edge_magnitudes, distance_research::euclidean_distance::euclidean_distance_f64);
+        }
+        if args.contains(SKELETON_DIST) {
+            self.build_and_test_model(SKELETON_DIST, "", skeleton, distance_research::euclidean_distance::euclidean_distance);
+        }
+        if args.contains(CHAMFER) {
+            self.build_and_test_model(CHAMFER, "", Shape::edges, chamfer_distance);
+        }
+        if args.contains(HAUSDORFF) {
+            self.build_and_test_model(HAUSDORFF, "", Shape::edges, hausdorff_distance);
+        }
+        if args.contains(DIRECTED_HAUSDORFF) {
+            self.build_and_test_model(DIRECTED_HAUSDORFF, "", Shape::edges, directed_hausdorff_distance);
+        }
+        if args.contains(MODIFIED_HAUSDORFF) {
+            self.build_and_test_model(MODIFIED_HAUSDORFF, "", Shape::edges, modified_hausdorff_distance);
+        }
+        if args.contains(CHAMFER_INK) {
+            self.build_and_test_model(CHAMFER_INK, "", Shape::thresholded, chamfer_distance);
+        }
+        if args.contains(HAUSDORFF_INK) {
+            self.build_and_test_model(HAUSDORFF_INK, "", Shape::thresholded, hausdorff_distance);
+        }
+        if args.contains(DIRECTED_HAUSDORFF_INK) {
+            self.build_and_test_model(DIRECTED_HAUSDORFF_INK, "", Shape::thresholded, directed_hausdorff_distance);
+        }
+        if args.contains(MODIFIED_HAUSDORFF_INK) {
+            self.build_and_test_model(MODIFIED_HAUSDORFF_INK, "", Shape::thresholded, modified_hausdorff_distance);
+        }
+        if args.contains(TANGENT) {
+            self.build_and_test_model(TANGENT, &format!("sigma={}", TANGENT_SIGMA), TangentImage::new, tangent_distance);
+        }
+        if args.contains(TANGENT_ONE_SIDED) {
+            self.build_and_test_model(TANGENT_ONE_SIDED, &format!("sigma={}", TANGENT_SIGMA), TangentImage::new, one_sided_tangent_distance);
+        }
+        if args.contains(IDM) {
+            let idm = Idm::new();
+            self.build_and_test_model(IDM, "", intensity_channels, |c1, c2| idm.distance(c1, c2));
+        }
+        if args.contains(IDM_SOBEL) {
+            let idm = Idm::new();
+            self.build_and_test_model(IDM_SOBEL, "", gradient_channels, |c1, c2| idm.distance(c1, c2));
+        }
+        if args.contains(SHIFT_EUCLIDEAN) {
+            self.build_and_test_model(SHIFT_EUCLIDEAN, "", |v| v.clone(), |img1, img2| distance_research::euclidean_distance::shift_tolerant_distance(img1, img2, SHIFT_RADIUS));
+        }
+        if args.contains(MANHATTAN) {
+            self.build_and_test_model(MANHATTAN, "", |v| v.clone(), manhattan_distance);
+        }
+        if args.contains(CHEBYSHEV) {
+            self.build_and_test_model(CHEBYSHEV, "", |v| v.clone(), chebyshev_distance);
+        }
+        if args.contains(MINKOWSKI) {
+            self.build_and_test_model(MINKOWSKI, "", |v| v.clone(), |img1, img2| minkowski_distance(img1, img2, MINKOWSKI_P));
+        }
+        if args.contains(COSINE) {
+            self.build_and_test_model(COSINE, "", |v| v.clone(), cosine_distance);
+        }
+        if args.contains(PEARSON) {
+            self.build_and_test_model(PEARSON, "", |v| v.clone(), pearson_distance);
+        }
+        if args.contains(CHI_SQUARED) {
+            self.build_and_test_model(CHI_SQUARED, "", |v| v.clone(), chi_squared_distance);
+        }
+        if args.contains(HISTOGRAM_INTERSECTION) {
+            self.build_and_test_model(HISTOGRAM_INTERSECTION, "", |v| v.clone(), histogram_intersection_distance);
+        }
+        if args.contains(CANBERRA) {
+            self.build_and_test_model(CANBERRA, "", |v| v.clone(), canberra_distance);
+        }
+        if args.contains(SSIM) {
+            self.build_and_test_model(SSIM, &format!("window={}", SSIM_WINDOW), |v| v.clone(), ssim_dissimilarity);
+        }
+        if args.contains(MS_SSIM) {
+            self.build_and_test_model(MS_SSIM, &format!("window={} scales={}", SSIM_WINDOW, MS_SSIM_SCALES), |v| v.clone(), ms_ssim_dissimilarity);
+        }
+        if args.contains(SINKHORN) {
+            let (width, height) = (self.training[0].1.width(), self.training[0].1.height());
+            let kernel = SinkhornKernel::new(width, height, SINKHORN_EPSILON);
+            self.build_and_test_model(SINKHORN, &format!("slices={}", NUM_SLICES), |img| MassDistribution::new(img, NUM_SLICES),
+                                      |m1, m2| sinkhorn_distance(m1, m2, &kernel, SINKHORN_ITERATIONS));
+        }
+        if args.contains(SLICED_WASSERSTEIN) {
+            self.build_and_test_model(SLICED_WASSERSTEIN, &format!("slices={}", NUM_SLICES), |img| MassDistribution::new(img, NUM_SLICES), sliced_wasserstein_distance);
         }
         if args.contains(COMPARE_KERNELS) {
-            self.build_and_test_converting_all(COMPARE_KERNELS, |images| images.iter().map(|(label, img)| (*label, kernelize_single_image(img, 8, 3))).collect(), best_match_distance);
+            self.build_and_test_converting_all(COMPARE_KERNELS, "kernels=8 size=3", |images| images.iter().map(|(label, img)| (*label, kernelize_single_image(img, 8, 3))).collect(), best_match_distance);
         }
         if args.contains(COMPARE_KEYPOINTS) {
-            self.build_and_test_converting_all(COMPARE_KEYPOINTS, |images| images.iter().map(|(label, img)| (*label, find_keypoints(img, 8, 3, 64))).collect(), closest_for_all);
+            self.build_and_test_converting_all(COMPARE_KEYPOINTS, "kernels=8 size=3 keypoints=64", |images| images.iter().map(|(label, img)| (*label, find_keypoints(img, 8, 3, 64))).collect(), closest_for_all);
+        }
+        if args.contains(KEYPOINT_ASSIGNMENT) {
+            self.build_and_test_converting_all(KEYPOINT_ASSIGNMENT, "kernels=8 size=3 keypoints=64", |images| images.iter().map(|(label, img)| (*label, find_keypoints(img, 8, 3, 64))).collect(),
+                                               |g1, g2| assignment_distance(g1, g2, PointMetric::SquaredEuclidean, UNMATCHED_DISTANCE));
+        }
+        if args.contains(KEYPOINT_ASSIGNMENT_L1) {
+            self.build_and_test_converting_all(KEYPOINT_ASSIGNMENT_L1, "kernels=8 size=3 keypoints=64", |images| images.iter().map(|(label, img)| (*label, find_keypoints(img, 8, 3, 64))).collect(),
+                                               |g1, g2| assignment_distance(g1, g2, PointMetric::Manhattan, UNMATCHED_DISTANCE));
+        }
+        let weight = self.appearance_weight;
+        let described_variants = [
+            (KEYPOINT_PATCHES, DescriptorKind::Patch(DESCRIPTOR_SIDE)),
+            (KEYPOINT_BRIEF, DescriptorKind::Brief(Descriptor::equidistant(DESCRIPTOR_SIDE, DESCRIPTOR_SIDE, DESCRIPTOR_SIDE / 3, DESCRIPTOR_SIDE / 3))),
+            (KEYPOINT_GRADIENTS, DescriptorKind::GradientHistogram {side: DESCRIPTOR_SIDE, bins: HISTOGRAM_BINS})
+        ];
+        for (label, kind) in described_variants.iter() {
+            if args.contains(*label) {
+                self.build_and_test_converting_all(label, "kernels=8 size=3 keypoints=64", |images| images.iter().map(|(label, img)| (*label, find_described_keypoints(img, 8, 3, 64, kind))).collect(),
+                                                   |k1, k2| closest_described_for_all(k1, k2, weight));
+            }
         }
     }
 
     fn build_and_test_descriptor(&mut self, descriptor_name: &str) {
         let descriptor = self.get_descriptor(descriptor_name);
-        self.build_and_test_model(descriptor_name, |img| descriptor.apply_to(img), bits::distance);
+        self.build_and_test_model(descriptor_name, "", |img| descriptor.apply_to(img), bits::distance);
     }
 
     fn build_and_test_patch(&mut self, label: &str, patch_size: usize) {
-        self.build_and_test_model(label, |img| patchify(img, patch_size), bits::distance);
+        self.build_and_test_model(label, &format!("size={}", patch_size), |img| patchify(img, patch_size), bits::distance);
     }
 
-    pub fn permuted(&self, permutation: &Vec<usize>) -> ExperimentData {
+    // Every setting carries over to an experiment on other images, with no errors recorded yet, so
+    // derived experiments build on this rather than copying fields themselves.
+    pub fn with_images(&self, training: Vec<(u8,Image)>, testing: Vec<(u8,Image)>) -> ExperimentData {
         ExperimentData {
-            training: permuted_data_set(permutation, &self.training),
-            testing: permuted_data_set(permutation, &self.testing),
+            training,
+            testing,
             descriptors: self.descriptors.clone(),
-            errors: BTreeMap::new()
+            errors: BTreeMap::new(),
+            source: self.source.clone(),
+            cache: self.cache.clone(),
+            validation: self.validation,
+            preprocessing: self.preprocessing.clone(),
+            appearance_weight: self.appearance_weight
         }
     }
 
+    pub fn permuted(&self, permutation: &Vec<usize>) -> ExperimentData {
+        self.with_images(permuted_data_set(permutation, &self.training), permuted_data_set(permutation, &self.testing))
+    }
+
+    // Only the training images are augmented; testing stays as it was.
+    pub fn augmented(&self, pipeline: &AugmentationPipeline) -> ExperimentData {
+        self.with_images(pipeline.augment(&self.training), self.testing.clone())
+    }
+
+    // Only the testing images are perturbed; training stays clean.
+    pub fn perturbed(&self, perturbation: &Perturbation, seed: u64) -> ExperimentData {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.with_images(self.training.clone(), perturbed(&self.testing, perturbation, &mut rng))
+    }
+
     pub fn print_errors(&self) {
         for (k,v) in self.errors.iter() {
             println!("{}: {}%", k, v);
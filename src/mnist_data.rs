@@ -1,38 +1,71 @@
-use std::fs;
 use std::io;
-use std::io::Read;
 use std::ops::{AddAssign, Add};
+use std::path::{Path, PathBuf};
 use crate::timing::print_time_milliseconds;
+use crate::idx;
+use crate::idx::{IdxError, LoadMode};
+use crate::pixel::{Pixel, Rgb, clamp_to_u8};
 
 pub const IMAGE_DIMENSION: usize = 28;
 pub const IMAGE_BYTES: usize = IMAGE_DIMENSION * IMAGE_DIMENSION;
-const BASE_PATH: &str = "/Users/ferrer/Desktop/mnist_data/";
 
-pub fn load_data_set(file_prefix: &str) -> io::Result<Vec<(u8,Image)>> {
-    let train_images = format!("{}{}-images-idx3-ubyte", BASE_PATH, file_prefix);
-    let train_labels = format!("{}{}-labels-idx1-ubyte", BASE_PATH, file_prefix);
+pub fn image_file_name(file_prefix: &str) -> String {
+    format!("{}-images-idx3-ubyte", file_prefix)
+}
+
+pub fn label_file_name(file_prefix: &str) -> String {
+    format!("{}-labels-idx1-ubyte", file_prefix)
+}
+
+pub fn required_files(file_prefixes: &[&str]) -> Vec<String> {
+    file_prefixes.iter()
+        .flat_map(|prefix| vec![image_file_name(prefix), label_file_name(prefix)])
+        .collect()
+}
+
+pub fn load_data_set(data_dir: &Path, file_prefix: &str) -> io::Result<Vec<(u8,Image)>> {
+    load_data_set_with(data_dir, file_prefix, LoadMode::Buffered)
+}
+
+pub fn load_data_set_with(data_dir: &Path, file_prefix: &str, mode: LoadMode) -> io::Result<Vec<(u8,Image)>> {
+    let train_images = existing_idx_path(data_dir, &image_file_name(file_prefix))?;
+    let train_labels = existing_idx_path(data_dir, &label_file_name(file_prefix))?;
 
     let training_images = print_time_milliseconds(&format!("loading mnist {} images", file_prefix),
-                                                  || init_from_files(train_images.as_str(), train_labels.as_str()))?;
+                                                  || init_from_files_with(&train_images, &train_labels, mode))?;
 
     println!("Number of {} images: {}", file_prefix, training_images.len());
     Ok(training_images)
 }
 
+// A PixelImage built with new() grows as a square as pixels are added. One built with
+// with_width() keeps its width fixed and grows in height instead.
 #[derive(Clone, Debug, Default)]
-pub struct Image {
-    pixels: Vec<u8>,
-    side_size: usize,
+pub struct PixelImage<P> {
+    pixels: Vec<P>,
+    width: usize,
+    height: usize,
+    fixed_width: bool
 }
 
+// Grayscale images with one byte per pixel, as stored in the IDX files.
+pub type Image = PixelImage<u8>;
+pub type RgbImage = PixelImage<Rgb>;
+
 pub trait Grid<T> {
     fn add(&mut self, pixel: T);
     fn get(&self, x: usize, y: usize) -> T;
-    fn side(&self) -> usize;
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
     fn len(&self) -> usize;
 
+    fn side(&self) -> usize {
+        assert_eq!(self.width(), self.height(), "side() requires a square grid");
+        self.width()
+    }
+
     fn in_bounds(&self, x: isize, y: isize) -> bool {
-        x >= 0 && y >= 0 && x < self.side() as isize && y < self.side() as isize
+        x >= 0 && y >= 0 && x < self.width() as isize && y < self.height() as isize
     }
 
     fn option_get(&self, x: isize, y: isize) -> Option<T> {
@@ -40,29 +73,36 @@ pub trait Grid<T> {
     }
 
     fn x_y_iter(&self) -> ImageIterator<usize> {
-        ImageIterator::new(0, 0, self.side(), self.side(), 1)
+        ImageIterator::new(0, 0, self.width(), self.height(), 1)
     }
 
     fn x_y_step_iter(&self, step_size: usize) -> ImageIterator<usize> {
-        ImageIterator::new(0, 0, self.side(), self.side(), step_size)
+        ImageIterator::new(0, 0, self.width(), self.height(), step_size)
     }
 }
 
-impl Grid<u8> for Image {
-    fn add(&mut self, pixel: u8) {
+impl<P: Pixel> Grid<P> for PixelImage<P> {
+    fn add(&mut self, pixel: P) {
         self.pixels.push(pixel);
-        if self.pixels.len() > self.side_size.pow(2) {
-            self.side_size += 1;
+        if self.fixed_width {
+            self.height = self.pixels.len().div_ceil(self.width);
+        } else if self.pixels.len() > self.width * self.height {
+            self.width += 1;
+            self.height += 1;
         }
     }
 
-    fn get(&self, x: usize, y: usize) -> u8 {
+    fn get(&self, x: usize, y: usize) -> P {
         assert!(self.in_bounds(x as isize, y as isize));
-        self.pixels[y * self.side_size + x]
+        self.pixels[y * self.width + x]
     }
 
-    fn side(&self) -> usize {
-        self.side_size
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
     }
 
     fn len(&self) -> usize {
@@ -70,58 +110,108 @@ impl Grid<u8> for Image {
     }
 }
 
-impl Image {
+impl<P: Pixel> PixelImage<P> {
     pub fn new() -> Self {
         Default::default()
     }
 
-    pub fn filter(&self, filter_fn: fn(&Image, usize, usize)->u8) -> Self {
-        let mut result = Image::new();
+    pub fn with_width(width: usize) -> Self {
+        assert!(width > 0);
+        PixelImage {pixels: Vec::new(), width, height: 0, fixed_width: true}
+    }
+
+    // An empty image that will grow to the same shape as this one.
+    fn empty_like<Q: Pixel>(&self) -> PixelImage<Q> {
+        if self.fixed_width {PixelImage::with_width(self.width)} else {PixelImage::new()}
+    }
+
+    pub fn filter<Q: Pixel, F: Fn(&Self, usize, usize) -> Q>(&self, filter_fn: F) -> PixelImage<Q> {
+        let mut result = self.empty_like();
         for (x, y) in self.x_y_iter() {
             result.add(filter_fn(self, x, y));
         }
         result
     }
 
-    pub fn from_vec(v: &Vec<u8>) -> Image {
-        let mut result = Image::new();
+    pub fn map<Q: Pixel, F: Fn(P) -> Q>(&self, map_fn: F) -> PixelImage<Q> {
+        PixelImage {pixels: self.pixels.iter().map(|p| map_fn(*p)).collect(), width: self.width, height: self.height, fixed_width: self.fixed_width}
+    }
+
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    pub fn from_slice(pixels: &[P], width: usize, height: usize) -> Self {
+        assert_eq!(width * height, pixels.len());
+        PixelImage {pixels: pixels.to_vec(), width, height, fixed_width: true}
+    }
+
+    pub fn from_vec(v: &Vec<P>) -> Self {
+        let mut result = PixelImage::new();
         v.iter().for_each(|p| result.add(*p));
         result
     }
 
-    pub fn permuted(&self, permutation: &[usize]) -> Image {
+    pub fn permuted(&self, permutation: &[usize]) -> Self {
         assert_eq!(self.pixels.len(), permutation.len());
-        let mut result = Image::new();
-        for index in permutation {
-            result.add(self.pixels[*index]);
-        }
+        let pixels: Vec<P> = permutation.iter().map(|index| self.pixels[*index]).collect();
+        PixelImage::from_slice(&pixels, self.width(), self.height())
+    }
+
+    pub fn transposed(&self) -> Self {
+        let mut result = PixelImage::with_width(self.height());
+        ImageIterator::new(0, 0, self.height(), self.width(), 1)
+            .for_each(|(x, y)| result.add(self.get(y, x)));
         result
     }
 
-    pub fn shrunken(&self, shrink: usize) -> Image {
-        let mut result = Image::new();
-        let target_side = self.side() / shrink;
-        ImageIterator::new(0, 0, target_side, target_side, 1)
-            .for_each(|(x, y)| result.add(self.subimage_mean(x, y, shrink)));
+    // Nearest-neighbor upscaling: each pixel becomes a factor x factor block.
+    pub fn enlarged(&self, factor: usize) -> Self {
+        let mut result = PixelImage::with_width(self.width() * factor);
+        ImageIterator::new(0, 0, self.width() * factor, self.height() * factor, 1)
+            .for_each(|(x, y)| result.add(self.get(x / factor, y / factor)));
         result
     }
 
-    pub fn subimage(&self, x_center: usize, y_center: usize, side: usize) -> Image {
-        let mut result = Image::new();
+    pub fn subimage(&self, x_center: usize, y_center: usize, side: usize) -> Self {
+        let mut result = PixelImage::with_width(side);
         ImageIterator::centered(x_center as isize, y_center as isize, side as isize, side as isize, 1)
-            .for_each(|(x, y)| result.add(self.option_get(x, y).unwrap_or(0)));
+            .for_each(|(x, y)| result.add(self.option_get(x, y).unwrap_or_default()));
         assert_eq!(side, result.side());
         result
     }
+}
 
+impl Image {
+    pub fn shrunken(&self, shrink: usize) -> Image {
+        let mut result = Image::with_width(self.width() / shrink);
+        ImageIterator::new(0, 0, self.width() / shrink, self.height() / shrink, 1)
+            .for_each(|(x, y)| result.add(self.subimage_mean(x, y, shrink)));
+        result
+    }
+
+    // Mean of the side x side block at block coordinates (x, y). The sum is a u32, as a u16
+    // overflows for blocks of 17x17 and up.
     fn subimage_mean(&self, x: usize, y: usize, side: usize) -> u8 {
-        let mut sum: u16 = 0;
-        for i in x..x + side {
-            for j in y..y + side {
-                sum += self.get(i, j) as u16;
+        let mut sum: u32 = 0;
+        for i in x * side..(x + 1) * side {
+            for j in y * side..(y + 1) * side {
+                sum += self.get(i, j) as u32;
             }
         }
-        (sum / side.pow(2) as u16) as u8
+        (sum / side.pow(2) as u32) as u8
+    }
+
+    pub fn to_u16(&self) -> PixelImage<u16> {
+        self.map(|p| p as u16)
+    }
+
+    pub fn to_f32(&self) -> PixelImage<f32> {
+        self.map(|p| p as f32)
+    }
+
+    pub fn to_rgb(&self) -> RgbImage {
+        self.map(Rgb::gray)
     }
 
     #[cfg(test)]
@@ -130,15 +220,54 @@ impl Image {
         self.x_y_iter().for_each(|(x, y)| sum += self.get(x, y) as u16);
         (sum / self.pixels.len() as u16) as u8
     }
+
+    #[cfg(test)]
+    pub fn test_pattern(width: usize, height: usize) -> Image {
+        let mut result = Image::with_width(width);
+        ImageIterator::new(0, 0, width, height, 1)
+            .for_each(|(x, y)| result.add(((x * 31 + y * 17) % 256) as u8));
+        result
+    }
+}
+
+impl PixelImage<u16> {
+    pub fn clamped_to_u8(&self) -> Image {
+        self.map(|p| p.min(u8::MAX as u16) as u8)
+    }
+
+    // Linearly rescales 0..=max onto 0..=255.
+    pub fn scaled_to_u8(&self, max: u16) -> Image {
+        self.map(|p| (p.min(max) as u32 * u8::MAX as u32 / max.max(1) as u32) as u8)
+    }
 }
 
-impl PartialEq for Image {
+impl PixelImage<f32> {
+    pub fn clamped_to_u8(&self) -> Image {
+        self.map(clamp_to_u8)
+    }
+
+    // Linearly rescales the image's own minimum and maximum onto 0..=255.
+    pub fn normalized_to_u8(&self) -> Image {
+        let min = self.pixels.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = self.pixels.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let range = if max > min {max - min} else {1.0};
+        self.map(|p| clamp_to_u8((p - min) * u8::MAX as f32 / range))
+    }
+}
+
+impl RgbImage {
+    pub fn to_luma(&self) -> Image {
+        self.map(|p| p.luma())
+    }
+}
+
+impl<P: Pixel> PartialEq for PixelImage<P> {
     fn eq(&self, other: &Self) -> bool {
-        self.side_size == other.side_size && self.pixels.len() == other.pixels.len() && (0..self.pixels.len()).all(|i| self.pixels[i] == other.pixels[i])
+        self.width == other.width && self.height == other.height && self.pixels == other.pixels
     }
 }
 
-impl Eq for Image {}
+impl<P: Pixel + Eq> Eq for PixelImage<P> {}
 
 pub fn image_mean(images: &Vec<&Image>) -> Image {
     assert!(!images.is_empty());
@@ -150,7 +279,7 @@ pub fn image_mean(images: &Vec<&Image>) -> Image {
         }
     }
 
-    let mut result = Image::new();
+    let mut result = images[0].empty_like();
     for sum in sums {
         result.add((sum / images.len()) as u8);
     }
@@ -197,58 +326,35 @@ impl<N: Copy + AddAssign + Add<Output=N> + Eq + Ord> Iterator for ImageIterator<
     }
 }
 
-pub fn init_from_files(image_file_name: &str, label_file_name: &str) -> io::Result<Vec<(u8,Image)>> {
-    let bytes: Vec<u8> = read_label_file(label_file_name)?;
-    read_image_file(image_file_name, &bytes)
+fn existing_idx_path(data_dir: &Path, file_name: &str) -> io::Result<PathBuf> {
+    idx::existing_path(data_dir, file_name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found in {}", file_name, data_dir.display())))
 }
 
-pub fn discard(items: &[(u8,Image)], shrink: usize) -> Vec<(u8,Image)> {
-    let mut result: Vec<(u8,Image)> = Vec::new();
-    for i in 0..items.len() {
-        if i % shrink == 0 {
-            result.push(items[i].clone())
-        }
-    }
-    result
+pub fn init_from_files<P: AsRef<Path>>(image_file_name: P, label_file_name: P) -> Result<Vec<(u8,Image)>, IdxError> {
+    init_from_files_with(image_file_name, label_file_name, LoadMode::Buffered)
 }
 
-fn read_label_file(label_file_name: &str) -> io::Result<Vec<u8>> {
-    let fin = fs::File::open(label_file_name)?;
-    let mut bytes: Vec<u8> = Vec::new();
-    let mut header_bytes_left = 8;
-    for b in fin.bytes() {
-        if header_bytes_left > 0 {
-            header_bytes_left -= 1;
-        } else {
-            let b = b?;
-            bytes.push(b);
-        }
-    }
-    Ok(bytes)
+pub fn init_from_files_with<P: AsRef<Path>>(image_file_name: P, label_file_name: P, mode: LoadMode) -> Result<Vec<(u8,Image)>, IdxError> {
+    let labels = idx::load_labels(label_file_name, mode)?;
+    let images = idx::load_images(image_file_name, mode)?;
+    idx::zip_labels(labels, images)
 }
 
-fn read_image_file(image_file_name: &str, labels: &[u8]) -> io::Result<Vec<(u8,Image)>> {
-    let fin = fs::File::open(image_file_name)?;
-    let mut images: Vec<(u8,Image)> = Vec::new();
-    let mut image: Image = Image::new();
-    let mut header_bytes_left = 16;
-    for b in fin.bytes() {
-        if header_bytes_left > 0 {
-            header_bytes_left -= 1;
-        } else {
-            image.add(b?);
-            if image.len() == IMAGE_BYTES {
-                images.push((labels[images.len()], image));
-                image = Image::new();
-            }
+pub fn discard<T: Clone>(items: &[(u8,T)], shrink: usize) -> Vec<(u8,T)> {
+    let mut result: Vec<(u8,T)> = Vec::new();
+    for i in 0..items.len() {
+        if i % shrink == 0 {
+            result.push(items[i].clone())
         }
     }
-    Ok(images)
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::rectangular_image;
 
     #[test]
     fn test_img() {
@@ -293,6 +399,74 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rectangular() {
+        let img = Image::from_slice(&(0..6).collect::<Vec<u8>>(), 3, 2);
+        assert_eq!((3, 2), (img.width(), img.height()));
+        assert_eq!(5, img.get(2, 1));
+        assert!(img.in_bounds(2, 1) && !img.in_bounds(1, 2));
+        assert_eq!(6, img.x_y_iter().count());
+
+        let mut grown = Image::with_width(3);
+        (0..4).for_each(|p| grown.add(p));
+        assert_eq!((3, 2), (grown.width(), grown.height()));
+        assert_eq!(3, grown.get(0, 1));
+
+        let transposed = img.transposed();
+        assert_eq!((2, 3), (transposed.width(), transposed.height()));
+        assert_eq!(5, transposed.get(1, 2));
+        assert_eq!(img, transposed.transposed());
+    }
+
+    #[test]
+    fn test_rectangular_shrunken_subimage() {
+        let img = rectangular_image();
+        let shrunk = img.shrunken(4);
+        assert_eq!((8, 5), (shrunk.width(), shrunk.height()));
+        assert_eq!(img.subimage(30, 18, 4).pixels().iter().map(|p| *p as u32).sum::<u32>() / 16, shrunk.get(7, 4) as u32);
+        let bright = Image::from_slice(&[255; 20 * 20], 20, 20).shrunken(20);
+        assert_eq!(Image::from_slice(&[255], 1, 1), bright);
+        let sub = img.subimage(31, 19, 3);
+        assert_eq!(img.get(30, 18), sub.get(0, 0));
+        assert_eq!(0, sub.get(2, 2));
+        let mean = image_mean(&vec![&img, &img]);
+        assert_eq!(img, mean);
+    }
+
+    #[test]
+    fn test_pixel_types() {
+        let img = Image::from_slice(&[0, 100, 200, 255], 2, 2);
+        let wide = img.to_u16().map(|p| p * 4);
+        assert_eq!(1020, wide.get(1, 1));
+        assert_eq!(Image::from_slice(&[0, 255, 255, 255], 2, 2), wide.clamped_to_u8());
+        assert_eq!(img, wide.scaled_to_u8(1020));
+
+        let float = img.to_f32().map(|p| p / 2.0 - 10.0);
+        assert_eq!(40.0, float.get(1, 0));
+        assert_eq!(Image::from_slice(&[0, 40, 90, 118], 2, 2), float.clamped_to_u8());
+        assert_eq!(img, float.normalized_to_u8());
+
+        let rgb = img.to_rgb();
+        assert_eq!(Rgb::gray(200), rgb.get(0, 1));
+        assert_eq!(img, rgb.to_luma());
+        assert_eq!((2, 2), (rgb.subimage(0, 0, 2).width(), rgb.subimage(0, 0, 2).height()));
+    }
+
+    #[test]
+    fn test_enlarged() {
+        let img = Image::from_slice(&[1, 2, 3, 4, 5, 6], 3, 2);
+        let big = img.enlarged(2);
+        assert_eq!((6, 4), (big.width(), big.height()));
+        assert_eq!(&[1, 1, 2, 2, 3, 3, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 4, 4, 5, 5, 6, 6], big.pixels());
+    }
+
+    #[test]
+    fn test_transposed() {
+        let img = Image::from_vec(&(1..10).collect());
+        assert_eq!(Image::from_vec(&vec![1, 4, 7, 2, 5, 8, 3, 6, 9]), img.transposed());
+        assert_eq!(img, img.transposed().transposed());
+    }
+
     #[test]
     fn test_subimage() {
         let img = Image::from_vec(&(1..16).collect());
@@ -0,0 +1,202 @@
+//! Grayscale morphology with arbitrary structuring elements, Otsu thresholding, and Zhang-Suen
+//! thinning, for distances that compare strokes rather than intensities.
+//!
+//! Binary images hold 0 and 255. On those, grayscale erosion and dilation are exactly the binary
+//! operations, so the same functions serve both.
+
+use crate::mnist_data::{Image, Grid};
+
+// Offsets from the center pixel; the neighborhood each output pixel draws on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructuringElement {
+    offsets: Vec<(isize, isize)>
+}
+
+impl StructuringElement {
+    pub fn from_offsets(offsets: Vec<(isize, isize)>) -> Self {
+        assert!(!offsets.is_empty(), "structuring element needs at least one offset");
+        StructuringElement {offsets}
+    }
+
+    fn within<F: Fn(isize, isize) -> bool>(radius: isize, inside: F) -> Self {
+        StructuringElement::from_offsets((-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter(|(dx, dy)| inside(*dx, *dy))
+            .collect())
+    }
+
+    pub fn square(radius: isize) -> Self {
+        StructuringElement::within(radius, |_, _| true)
+    }
+
+    pub fn cross(radius: isize) -> Self {
+        StructuringElement::within(radius, |dx, dy| dx == 0 || dy == 0)
+    }
+
+    pub fn disk(radius: isize) -> Self {
+        StructuringElement::within(radius, |dx, dy| dx * dx + dy * dy <= radius * radius)
+    }
+
+    pub fn offsets(&self) -> &[(isize, isize)] {
+        &self.offsets
+    }
+
+    // Pixels of the neighborhood that fall outside the image are left out.
+    fn neighborhood<'a>(&'a self, img: &'a Image, x: usize, y: usize) -> impl Iterator<Item=u8> + 'a {
+        self.offsets.iter().filter_map(move |(dx, dy)| img.option_get(x as isize + dx, y as isize + dy))
+    }
+}
+
+pub fn eroded(img: &Image, element: &StructuringElement) -> Image {
+    img.filter(|img, x, y| element.neighborhood(img, x, y).min().unwrap_or(0))
+}
+
+pub fn dilated(img: &Image, element: &StructuringElement) -> Image {
+    img.filter(|img, x, y| element.neighborhood(img, x, y).max().unwrap_or(0))
+}
+
+// Removes bright details smaller than the element.
+pub fn opened(img: &Image, element: &StructuringElement) -> Image {
+    dilated(&eroded(img, element), element)
+}
+
+// Fills dark gaps smaller than the element.
+pub fn closed(img: &Image, element: &StructuringElement) -> Image {
+    eroded(&dilated(img, element), element)
+}
+
+// Pixels above threshold become 255; the rest become 0.
+pub fn binarized(img: &Image, threshold: u8) -> Image {
+    img.map(|p| if p > threshold {u8::MAX} else {0})
+}
+
+// The threshold that maximizes the between-class variance of the pixels at or below it and
+// those above it.
+pub fn otsu_threshold(img: &Image) -> u8 {
+    let mut histogram = [0usize; 256];
+    img.x_y_iter().for_each(|(x, y)| histogram[img.get(x, y) as usize] += 1);
+    let total = histogram.iter().sum::<usize>() as f64;
+    let total_sum: f64 = histogram.iter().enumerate().map(|(value, count)| (value * count) as f64).sum();
+    let (mut below, mut below_sum) = (0.0, 0.0);
+    let (mut best, mut best_variance) = (0, -1.0);
+    for (threshold, count) in histogram.iter().enumerate().take(u8::MAX as usize) {
+        below += *count as f64;
+        below_sum += (threshold * count) as f64;
+        let above = total - below;
+        if below == 0.0 || above == 0.0 {
+            continue;
+        }
+        let mean_difference = below_sum / below - (total_sum - below_sum) / above;
+        let variance = below * above * mean_difference * mean_difference;
+        if variance > best_variance {
+            best = threshold as u8;
+            best_variance = variance;
+        }
+    }
+    best
+}
+
+// Zhang and Suen (1984). Nonzero pixels are foreground; the result is a one-pixel-wide binary
+// skeleton.
+pub fn thinned(img: &Image) -> Image {
+    let mut current = binarized(img, 0);
+    loop {
+        let mut changed = false;
+        for first_pass in [true, false] {
+            let next = current.filter(|img, x, y| {
+                if img.get(x, y) > 0 && removable(img, x, y, first_pass) {0} else {img.get(x, y)}
+            });
+            changed |= next != current;
+            current = next;
+        }
+        if !changed {
+            return current;
+        }
+    }
+}
+
+fn removable(img: &Image, x: usize, y: usize, first_pass: bool) -> bool {
+    let on = |dx: isize, dy: isize| img.option_get(x as isize + dx, y as isize + dy).is_some_and(|p| p > 0);
+    // P2 through P9, clockwise from north.
+    let p = [on(0, -1), on(1, -1), on(1, 0), on(1, 1), on(0, 1), on(-1, 1), on(-1, 0), on(-1, -1)];
+    let neighbors = p.iter().filter(|n| **n).count();
+    let transitions = (0..8).filter(|i| !p[*i] && p[(i + 1) % 8]).count();
+    let (north, east, south, west) = (p[0], p[2], p[4], p[6]);
+    // The first pass peels south-east boundary points and north-west corners; the second the reverse.
+    let sides_clear = if first_pass {
+        !(east && south && (north || west))
+    } else {
+        !(north && west && (east || south))
+    };
+    (2..=6).contains(&neighbors) && transitions == 1 && sides_clear
+}
+
+// Thins the foreground found by Otsu's threshold.
+pub fn skeleton(img: &Image) -> Image {
+    thinned(&binarized(img, otsu_threshold(img)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 9x9 image holding a filled square from (2, 2) to (6, 6), plus one stray pixel.
+    fn square_and_speck() -> Image {
+        let mut pixels = vec![0; 81];
+        for y in 2..=6 {
+            for x in 2..=6 {
+                pixels[y * 9 + x] = 200;
+            }
+        }
+        pixels[8 * 9] = 200;
+        Image::from_slice(&pixels, 9, 9)
+    }
+
+    fn count_on(img: &Image) -> usize {
+        img.x_y_iter().filter(|(x, y)| img.get(*x, *y) > 0).count()
+    }
+
+    #[test]
+    fn test_erode_dilate() {
+        let img = square_and_speck();
+        let square = StructuringElement::square(1);
+        assert_eq!(9, count_on(&eroded(&img, &square)));
+        assert_eq!(49 + 3, count_on(&dilated(&img, &square)));
+        assert_eq!(25, count_on(&opened(&img, &square)));
+        assert_eq!(5, StructuringElement::cross(1).offsets().len());
+        assert_eq!(13, StructuringElement::disk(2).offsets().len());
+    }
+
+    #[test]
+    fn test_close_fills_hole() {
+        let mut pixels = vec![200; 25];
+        pixels[12] = 0;
+        let holed = Image::from_slice(&pixels, 5, 5);
+        assert_eq!(Image::from_slice(&[200; 25], 5, 5), closed(&holed, &StructuringElement::cross(1)));
+    }
+
+    #[test]
+    fn test_otsu() {
+        let pixels: Vec<u8> = (0..100).map(|i| if i % 2 == 0 {20 + (i % 7) as u8} else {180 + (i % 5) as u8}).collect();
+        let img = Image::from_slice(&pixels, 10, 10);
+        let threshold = otsu_threshold(&img);
+        assert!((26..180).contains(&threshold));
+        assert_eq!(50, count_on(&binarized(&img, threshold)));
+    }
+
+    #[test]
+    fn test_thinning() {
+        // A bar three pixels thick thins to a single horizontal line.
+        let mut pixels = vec![0; 12 * 7];
+        for y in 2..=4 {
+            for x in 1..=10 {
+                pixels[y * 12 + x] = 255;
+            }
+        }
+        let bar = Image::from_slice(&pixels, 12, 7);
+        let thin = skeleton(&bar);
+        assert!(count_on(&thin) > 0);
+        assert!(thin.x_y_iter().all(|(x, y)| thin.get(x, y) == 0 || y == 3));
+        assert_eq!(thin, thinned(&thin));
+    }
+}
@@ -9,4 +9,21 @@ pub fn patchify(img: &Image, patch_size: usize) -> BitArray {
         }
     }
     patch
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{rectangular_image, RECT_WIDTH, RECT_HEIGHT};
+
+    #[test]
+    fn test_neighbor_comparisons() {
+        let img = rectangular_image();
+        let patch = patchify(&img, 3);
+        assert_eq!(RECT_WIDTH * RECT_HEIGHT * 9, patch.len());
+        // The bottom right pixel (4) is brighter than the 5 neighbors outside the image, which count
+        // as 0, but not than itself or its 3 neighbors inside.
+        assert_eq!(4, img.get(RECT_WIDTH - 1, RECT_HEIGHT - 1));
+        assert_eq!(5, (patch.len() - 9..patch.len()).filter(|i| patch.is_set(*i)).count());
+    }
+}
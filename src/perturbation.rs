@@ -0,0 +1,200 @@
+//! Test-time perturbations of increasing severity, for measuring how gracefully each distance
+//! degrades. Training images stay clean; only the testing images are perturbed.
+//!
+//! Error rates are collected into curves, one per variant, and written as CSV files with the
+//! columns `perturbation,severity,error_percent`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand_distr::{Normal, Distribution};
+use crate::augmentation::{translated, rotated};
+use crate::mnist_data::{Image, Grid};
+use crate::pixel::clamp_to_u8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Perturbation {
+    // Moves the image by this many pixels left, right, up, or down, chosen at random.
+    Shift(usize),
+    Rotation(f64),
+    GaussianNoise(f64),
+    // Each pixel independently becomes 0 or 255 with this probability.
+    SaltAndPepper(f64),
+    // A black square of this side at a random position inside the image.
+    Occlusion(usize),
+    // Scales each pixel's difference from mid-gray by this factor.
+    Contrast(f64),
+    Brightness(f64)
+}
+
+impl Perturbation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Perturbation::Shift(_) => "shift",
+            Perturbation::Rotation(_) => "rotation",
+            Perturbation::GaussianNoise(_) => "gaussian_noise",
+            Perturbation::SaltAndPepper(_) => "salt_and_pepper",
+            Perturbation::Occlusion(_) => "occlusion",
+            Perturbation::Contrast(_) => "contrast",
+            Perturbation::Brightness(_) => "brightness"
+        }
+    }
+
+    pub fn severity(&self) -> f64 {
+        match self {
+            Perturbation::Shift(pixels) | Perturbation::Occlusion(pixels) => *pixels as f64,
+            Perturbation::Rotation(value) | Perturbation::GaussianNoise(value) | Perturbation::SaltAndPepper(value)
+            | Perturbation::Contrast(value) | Perturbation::Brightness(value) => *value
+        }
+    }
+
+    pub fn apply(&self, img: &Image, rng: &mut StdRng) -> Image {
+        match self {
+            Perturbation::Shift(pixels) => {
+                let n = *pixels as isize;
+                let (dx, dy) = [(n, 0), (-n, 0), (0, n), (0, -n)][rng.gen_range(0..4)];
+                translated(img, dx, dy)
+            }
+            Perturbation::Rotation(degrees) => rotated(img, *degrees),
+            Perturbation::GaussianNoise(sigma) => {
+                let noise = Normal::new(0.0, *sigma as f32).unwrap();
+                per_pixel(img, |p| clamp_to_u8(p as f32 + noise.sample(rng)))
+            }
+            Perturbation::SaltAndPepper(probability) => per_pixel(img, |p| {
+                if rng.gen_bool(*probability) {
+                    if rng.gen_bool(0.5) {u8::MAX} else {0}
+                } else {
+                    p
+                }
+            }),
+            Perturbation::Occlusion(side) => {
+                let (width, height) = ((*side).min(img.width()), (*side).min(img.height()));
+                let x_start = rng.gen_range(0..=img.width() - width);
+                let y_start = rng.gen_range(0..=img.height() - height);
+                img.filter(|img, x, y| {
+                    let inside = x >= x_start && x < x_start + width && y >= y_start && y < y_start + height;
+                    if inside {0} else {img.get(x, y)}
+                })
+            }
+            Perturbation::Contrast(factor) => img.map(|p| clamp_to_u8((p as f32 - 128.0) * *factor as f32 + 128.0)),
+            Perturbation::Brightness(offset) => img.map(|p| clamp_to_u8(p as f32 + *offset as f32))
+        }
+    }
+}
+
+// Like Image::map(), but the function may draw from a random number generator.
+fn per_pixel<F: FnMut(u8) -> u8>(img: &Image, mut pixel_fn: F) -> Image {
+    let pixels: Vec<u8> = img.pixels().iter().map(|p| pixel_fn(*p)).collect();
+    Image::from_slice(&pixels, img.width(), img.height())
+}
+
+// Each curve lists one kind of perturbation in order of increasing severity.
+pub fn default_curves() -> Vec<Vec<Perturbation>> {
+    vec![
+        vec![1, 2, 3, 4].into_iter().map(Perturbation::Shift).collect(),
+        vec![5.0, 10.0, 20.0, 30.0, 45.0].into_iter().map(Perturbation::Rotation).collect(),
+        vec![16.0, 32.0, 64.0, 96.0, 128.0].into_iter().map(Perturbation::GaussianNoise).collect(),
+        vec![0.02, 0.05, 0.1, 0.2, 0.3].into_iter().map(Perturbation::SaltAndPepper).collect(),
+        vec![4, 8, 12, 16].into_iter().map(Perturbation::Occlusion).collect(),
+        vec![0.8, 0.6, 0.4, 0.2].into_iter().map(Perturbation::Contrast).collect(),
+        vec![32.0, 64.0, 96.0, 128.0].into_iter().map(Perturbation::Brightness).collect()
+    ]
+}
+
+pub fn perturbed(images: &[(u8,Image)], perturbation: &Perturbation, rng: &mut StdRng) -> Vec<(u8,Image)> {
+    images.iter().map(|(label, img)| (*label, perturbation.apply(img, rng))).collect()
+}
+
+// (perturbation, severity, error percentage) rows for each variant.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RobustnessCurves {
+    rows: BTreeMap<String,Vec<(String,f64,f64)>>
+}
+
+impl RobustnessCurves {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, perturbation: &str, severity: f64, errors: &BTreeMap<String,f64>) {
+        for (variant, error) in errors.iter() {
+            self.rows.entry(variant.clone()).or_default().push((perturbation.to_string(), severity, *error));
+        }
+    }
+
+    pub fn rows(&self, variant: &str) -> Option<&Vec<(String,f64,f64)>> {
+        self.rows.get(variant)
+    }
+
+    // Writes dir/<variant>.csv for every variant, returning the paths written.
+    pub fn write_csv<P: AsRef<Path>>(&self, dir: P) -> io::Result<Vec<PathBuf>> {
+        fs::create_dir_all(&dir)?;
+        let mut written = Vec::new();
+        for (variant, rows) in self.rows.iter() {
+            let path = dir.as_ref().join(format!("{}.csv", variant));
+            let mut output = io::BufWriter::new(fs::File::create(&path)?);
+            writeln!(output, "perturbation,severity,error_percent")?;
+            for (perturbation, severity, error) in rows.iter() {
+                writeln!(output, "{},{},{}", perturbation, severity, error)?;
+            }
+            output.flush()?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use rand::SeedableRng;
+
+    fn constant(value: u8) -> Image {
+        Image::from_slice(&[value; 64], 8, 8)
+    }
+
+    #[test]
+    fn test_photometric() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(constant(168), Perturbation::Contrast(0.5).apply(&constant(208), &mut rng));
+        assert_eq!(constant(255), Perturbation::Brightness(100.0).apply(&constant(200), &mut rng));
+        let noisy = Perturbation::GaussianNoise(20.0).apply(&constant(128), &mut rng);
+        assert!(noisy.x_y_iter().any(|(x, y)| noisy.get(x, y) != 128));
+        let salted = Perturbation::SaltAndPepper(1.0).apply(&constant(128), &mut rng);
+        assert!(salted.x_y_iter().all(|(x, y)| salted.get(x, y) == 0 || salted.get(x, y) == 255));
+        assert_eq!(constant(128), Perturbation::SaltAndPepper(0.0).apply(&constant(128), &mut rng));
+    }
+
+    #[test]
+    fn test_geometric() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let occluded = Perturbation::Occlusion(3).apply(&constant(9), &mut rng);
+        assert_eq!(9, occluded.x_y_iter().filter(|(x, y)| occluded.get(*x, *y) == 0).count());
+        let covered = Perturbation::Occlusion(20).apply(&constant(9), &mut rng);
+        assert_eq!(constant(0), covered);
+        let shifted = Perturbation::Shift(2).apply(&constant(9), &mut rng);
+        assert_eq!(16, shifted.x_y_iter().filter(|(x, y)| shifted.get(*x, *y) == 0).count());
+        for curve in default_curves() {
+            assert!(curve.windows(2).all(|w| w[0].name() == w[1].name()));
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut curves = RobustnessCurves::new();
+        let errors: BTreeMap<String,f64> = vec![("baseline".to_string(), 3.5), ("patch".to_string(), 4.0)].into_iter().collect();
+        curves.add("rotation", 0.0, &errors);
+        curves.add("rotation", 10.0, &errors);
+        assert_eq!(2, curves.rows("patch").unwrap().len());
+        let dir = temp_path("robustness");
+        let written = curves.write_csv(&dir).unwrap();
+        assert_eq!(2, written.len());
+        assert_eq!("perturbation,severity,error_percent\nrotation,0,3.5\nrotation,10,3.5\n", fs::read_to_string(dir.join("baseline.csv")).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
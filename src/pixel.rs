@@ -0,0 +1,60 @@
+//! Pixel types that a `PixelImage` can hold. Grayscale images are `u8` as loaded from the IDX
+//! files. `u16` and `f32` keep intermediate results, such as Sobel magnitudes and kernel distances,
+//! without clipping them to a byte. `Rgb` holds three 8-bit channels.
+//!
+//! Converting between pixel types is always explicit; see the conversion methods on `PixelImage`.
+
+use std::fmt::Debug;
+
+pub trait Pixel: Copy + Default + PartialEq + Debug {}
+
+impl Pixel for u8 {}
+impl Pixel for u16 {}
+impl Pixel for f32 {}
+impl Pixel for Rgb {}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8
+}
+
+impl Rgb {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb {r, g, b}
+    }
+
+    pub fn gray(value: u8) -> Self {
+        Rgb::new(value, value, value)
+    }
+
+    // ITU-R BT.601 luma weights.
+    pub fn luma(&self) -> u8 {
+        (0.299 * self.r as f32 + 0.587 * self.g as f32 + 0.114 * self.b as f32).round() as u8
+    }
+}
+
+pub fn clamp_to_u8(value: f32) -> u8 {
+    value.round().max(0.0).min(u8::MAX as f32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luma() {
+        assert_eq!(0, Rgb::default().luma());
+        assert_eq!(255, Rgb::gray(255).luma());
+        assert_eq!(76, Rgb::new(255, 0, 0).luma());
+        assert_eq!(150, Rgb::new(0, 255, 0).luma());
+    }
+
+    #[test]
+    fn test_clamp() {
+        assert_eq!(0, clamp_to_u8(-3.0));
+        assert_eq!(3, clamp_to_u8(2.6));
+        assert_eq!(255, clamp_to_u8(1000.0));
+    }
+}
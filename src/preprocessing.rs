@@ -0,0 +1,186 @@
+//! Normalizing digit images before conversion: moment-based deskewing, recentering on the center
+//! of mass, and cropping to the ink's bounding box and rescaling it to fill a fixed box.
+//!
+//! Each step resamples bilinearly, as in `augmentation`. Blank images pass through unchanged.
+
+use crate::augmentation::{resampled, shifted};
+use crate::mnist_data::{Image, Grid};
+
+// Intensity-weighted moments: the center of mass and the second-order central moments.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Moments {
+    pub mass: f64,
+    pub x_mean: f64,
+    pub y_mean: f64,
+    pub mu20: f64,
+    pub mu02: f64,
+    pub mu11: f64
+}
+
+impl Moments {
+    // None if every pixel is 0.
+    pub fn of(img: &Image) -> Option<Moments> {
+        let weighted = || img.x_y_iter().map(|(x, y)| (x as f64, y as f64, img.get(x, y) as f64));
+        let mass: f64 = weighted().map(|(_, _, p)| p).sum();
+        if mass == 0.0 {
+            return None;
+        }
+        let x_mean = weighted().map(|(x, _, p)| x * p).sum::<f64>() / mass;
+        let y_mean = weighted().map(|(_, y, p)| y * p).sum::<f64>() / mass;
+        let central = |f: &dyn Fn(f64, f64) -> f64| weighted().map(|(x, y, p)| f(x - x_mean, y - y_mean) * p).sum::<f64>() / mass;
+        Some(Moments {mass, x_mean, y_mean, mu20: central(&|dx, _| dx * dx), mu02: central(&|_, dy| dy * dy), mu11: central(&|dx, dy| dx * dy)})
+    }
+
+    // Horizontal displacement per row that makes the ink's principal axis vertical.
+    pub fn skew(&self) -> f64 {
+        if self.mu02 > f64::EPSILON {self.mu11 / self.mu02} else {0.0}
+    }
+}
+
+fn center(img: &Image) -> (f64, f64) {
+    ((img.width() as f64 - 1.0) / 2.0, (img.height() as f64 - 1.0) / 2.0)
+}
+
+// Shears each row horizontally about the center of mass, which stays where it was.
+pub fn deskewed(img: &Image) -> Image {
+    match Moments::of(img) {
+        None => img.clone(),
+        Some(moments) => {
+            let skew = moments.skew();
+            resampled(img, |x, y| (x + skew * (y - moments.y_mean), y))
+        }
+    }
+}
+
+// Moves the center of mass to the center of the image.
+pub fn recentered(img: &Image) -> Image {
+    match Moments::of(img) {
+        None => img.clone(),
+        Some(moments) => {
+            let (cx, cy) = center(img);
+            shifted(img, cx - moments.x_mean, cy - moments.y_mean)
+        }
+    }
+}
+
+// (x_min, y_min, x_max, y_max) of the pixels brighter than threshold, inclusive.
+pub fn bounding_box(img: &Image, threshold: u8) -> Option<(usize, usize, usize, usize)> {
+    img.x_y_iter().filter(|(x, y)| img.get(*x, *y) > threshold).fold(None, |bounds, (x, y)| match bounds {
+        None => Some((x, y, x, y)),
+        Some((x0, y0, x1, y1)) => Some((x0.min(x), y0.min(y), x1.max(x), y1.max(y)))
+    })
+}
+
+// Scales the bounding box of the nonzero pixels, keeping its aspect ratio, so that it just fits
+// inside the image less margin pixels on every side, and centers it there.
+pub fn cropped_and_rescaled(img: &Image, margin: usize) -> Image {
+    match bounding_box(img, 0) {
+        None => img.clone(),
+        Some((x0, y0, x1, y1)) => {
+            let inner_width = img.width().saturating_sub(2 * margin).max(1) as f64;
+            let inner_height = img.height().saturating_sub(2 * margin).max(1) as f64;
+            let scale = (inner_width / (x1 - x0 + 1) as f64).min(inner_height / (y1 - y0 + 1) as f64);
+            let (box_x, box_y) = ((x0 + x1) as f64 / 2.0, (y0 + y1) as f64 / 2.0);
+            let (cx, cy) = center(img);
+            resampled(img, |x, y| (box_x + (x - cx) / scale, box_y + (y - cy) / scale))
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Preprocessing {
+    Deskew, Recenter,
+    // Uses a margin of 1/7 of the shorter side, which puts MNIST's 20x20 digit box in a 28x28 image.
+    Crop
+}
+
+impl Preprocessing {
+    pub fn all() -> Vec<Preprocessing> {
+        vec![Preprocessing::Deskew, Preprocessing::Recenter, Preprocessing::Crop]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preprocessing::Deskew => "deskew",
+            Preprocessing::Recenter => "recenter",
+            Preprocessing::Crop => "crop"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Preprocessing> {
+        Preprocessing::all().into_iter().find(|p| p.name() == name)
+    }
+
+    pub fn apply(&self, img: &Image) -> Image {
+        match self {
+            Preprocessing::Deskew => deskewed(img),
+            Preprocessing::Recenter => recentered(img),
+            Preprocessing::Crop => cropped_and_rescaled(img, img.width().min(img.height()) / 7)
+        }
+    }
+}
+
+// Applies the steps in order.
+pub fn preprocessed(img: &Image, steps: &[Preprocessing]) -> Image {
+    steps.iter().fold(img.clone(), |img, step| step.apply(&img))
+}
+
+pub fn preprocessed_all(images: &[(u8,Image)], steps: &[Preprocessing]) -> Vec<(u8,Image)> {
+    images.iter().map(|(label, img)| (*label, preprocessed(img, steps))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 9x9 image with a one-pixel stroke running from (2, 8) up to (6, 0), leaning right.
+    fn slanted() -> Image {
+        let mut pixels = vec![0; 81];
+        for y in 0..9 {
+            pixels[y * 9 + 6 - y / 2] = 255;
+        }
+        Image::from_slice(&pixels, 9, 9)
+    }
+
+    #[test]
+    fn test_moments() {
+        let mut pixels = vec![0; 25];
+        pixels[6] = 100;
+        pixels[8] = 100;
+        let moments = Moments::of(&Image::from_slice(&pixels, 5, 5)).unwrap();
+        assert_eq!((200.0, 2.0, 1.0), (moments.mass, moments.x_mean, moments.y_mean));
+        assert_eq!((1.0, 0.0, 0.0), (moments.mu20, moments.mu02, moments.mu11));
+        assert_eq!(None, Moments::of(&Image::from_slice(&[0; 4], 2, 2)));
+        assert!(Moments::of(&slanted()).unwrap().skew() < 0.0);
+    }
+
+    #[test]
+    fn test_deskew() {
+        let img = slanted();
+        let upright = deskewed(&img);
+        let before = Moments::of(&img).unwrap();
+        let after = Moments::of(&upright).unwrap();
+        assert!(after.mu11.abs() < before.mu11.abs() / 4.0);
+        assert!(after.mu20 < before.mu20);
+        assert!((after.y_mean - before.y_mean).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_recenter_and_crop() {
+        let mut pixels = vec![0; 100];
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            pixels[y * 10 + x] = 200;
+        }
+        let corner = Image::from_slice(&pixels, 10, 10);
+        let centered = Moments::of(&recentered(&corner)).unwrap();
+        assert!((centered.x_mean - 4.5).abs() < 1e-9 && (centered.y_mean - 4.5).abs() < 1e-9);
+        assert_eq!(Some((0, 0, 1, 1)), bounding_box(&corner, 0));
+        // Bilinear sampling blurs the rescaled box's edges a little past the margin.
+        assert_eq!(Some((1, 1, 8, 8)), bounding_box(&cropped_and_rescaled(&corner, 1), 100));
+        let blank = Image::from_slice(&[0; 100], 10, 10);
+        assert_eq!(blank, preprocessed(&blank, &Preprocessing::all()));
+        for step in Preprocessing::all() {
+            assert_eq!(Some(step), Preprocessing::from_name(step.name()));
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Subsampling labeled data sets. `mnist_data::discard` keeps every nth item, so its class balance
+//! depends on file order and it always picks the same items. These samplers are seeded instead, so a
+//! learning curve can be redrawn with different seeds while each draw stays reproducible.
+//!
+//! Every sampler keeps the chosen items in their original order.
+
+use std::collections::BTreeMap;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index;
+use crate::mnist_data::discard;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Subsampling {
+    Modulo, Random, Stratified, Balanced
+}
+
+impl Subsampling {
+    pub fn all() -> Vec<Subsampling> {
+        vec![Subsampling::Modulo, Subsampling::Random, Subsampling::Stratified, Subsampling::Balanced]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsampling::Modulo => "modulo",
+            Subsampling::Random => "random",
+            Subsampling::Stratified => "stratified",
+            Subsampling::Balanced => "balanced"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Subsampling> {
+        Subsampling::all().into_iter().find(|s| s.name() == name)
+    }
+
+    // Keeps about 1 of every shrink items, as discard() does. The seed is ignored by Modulo.
+    pub fn apply<T: Clone>(&self, items: &[(u8,T)], shrink: usize, seed: u64) -> Vec<(u8,T)> {
+        let size = items.len().div_ceil(shrink);
+        match self {
+            Subsampling::Modulo => discard(items, shrink),
+            Subsampling::Random => random_sample(items, size, seed),
+            Subsampling::Stratified => stratified_sample(items, &proportional_quotas(items, size), seed),
+            Subsampling::Balanced => {
+                // At least one per class, so a small sample never comes back empty.
+                let num_classes = class_counts(items).len().max(1);
+                balanced_sample(items, (size / num_classes).max(1), seed)
+            }
+        }
+    }
+}
+
+pub fn class_counts<T>(items: &[(u8,T)]) -> BTreeMap<u8,usize> {
+    let mut counts = BTreeMap::new();
+    for (label, _) in items.iter() {
+        *counts.entry(*label).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn chosen_indices(len: usize, amount: usize, rng: &mut StdRng) -> Vec<usize> {
+    let mut chosen = index::sample(rng, len, amount.min(len)).into_vec();
+    chosen.sort_unstable();
+    chosen
+}
+
+pub fn random_sample<T: Clone>(items: &[(u8,T)], size: usize, seed: u64) -> Vec<(u8,T)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    chosen_indices(items.len(), size, &mut rng).iter().map(|i| items[*i].clone()).collect()
+}
+
+// Draws quotas[label] items of each class; classes without a quota are left out.
+pub fn stratified_sample<T: Clone>(items: &[(u8,T)], quotas: &BTreeMap<u8,usize>, seed: u64) -> Vec<(u8,T)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut by_class: BTreeMap<u8,Vec<usize>> = BTreeMap::new();
+    for (i, (label, _)) in items.iter().enumerate() {
+        by_class.entry(*label).or_default().push(i);
+    }
+    let mut chosen: Vec<usize> = Vec::new();
+    for (label, positions) in by_class.iter() {
+        let quota = quotas.get(label).copied().unwrap_or(0);
+        chosen.extend(chosen_indices(positions.len(), quota, &mut rng).iter().map(|i| positions[*i]));
+    }
+    chosen.sort_unstable();
+    chosen.iter().map(|i| items[*i].clone()).collect()
+}
+
+// Same number of items from every class, or all of a class that has fewer.
+pub fn balanced_sample<T: Clone>(items: &[(u8,T)], per_class: usize, seed: u64) -> Vec<(u8,T)> {
+    let quotas = class_counts(items).keys().map(|label| (*label, per_class)).collect();
+    stratified_sample(items, &quotas, seed)
+}
+
+// Splits size among the classes in proportion to their counts, by largest remainder, so the quotas
+// add up to exactly size (or to items.len(), if that is smaller).
+pub fn proportional_quotas<T>(items: &[(u8,T)], size: usize) -> BTreeMap<u8,usize> {
+    let counts = class_counts(items);
+    let size = size.min(items.len());
+    let mut quotas: BTreeMap<u8,usize> = counts.iter()
+        .map(|(label, count)| (*label, count * size / items.len()))
+        .collect();
+    let mut by_remainder: Vec<(u8,usize)> = counts.iter()
+        .map(|(label, count)| (*label, count * size % items.len()))
+        .collect();
+    by_remainder.sort_by(|(l1, r1), (l2, r2)| r2.cmp(r1).then(l1.cmp(l2)));
+    let shortfall = size - quotas.values().sum::<usize>();
+    for (label, _) in by_remainder.iter().take(shortfall) {
+        *quotas.get_mut(label).unwrap() += 1;
+    }
+    quotas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnist_data::Image;
+
+    // Class 0 holds the first 60 items, class 1 the next 30, and class 2 the last 10.
+    fn skewed() -> Vec<(u8,usize)> {
+        (0..100).map(|i| (if i < 60 {0} else if i < 90 {1} else {2}, i)).collect()
+    }
+
+    #[test]
+    fn test_random_sample() {
+        let items = skewed();
+        let sample = random_sample(&items, 20, 7);
+        assert_eq!(20, sample.len());
+        assert_eq!(sample, random_sample(&items, 20, 7));
+        assert_ne!(sample, random_sample(&items, 20, 8));
+        assert!(sample.windows(2).all(|w| w[0].1 < w[1].1));
+        assert_eq!(100, random_sample(&items, 500, 7).len());
+    }
+
+    #[test]
+    fn test_stratified_sample() {
+        let items = skewed();
+        let quotas = proportional_quotas(&items, 10);
+        assert_eq!(vec![6, 3, 1], quotas.values().cloned().collect::<Vec<_>>());
+        let sample = stratified_sample(&items, &quotas, 3);
+        assert_eq!(quotas, class_counts(&sample));
+        assert_eq!(vec![5, 3, 1], proportional_quotas(&items, 9).values().cloned().collect::<Vec<_>>());
+        assert_eq!(vec![4, 2, 1], proportional_quotas(&items, 7).values().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_balanced_sample() {
+        let items = skewed();
+        let sample = balanced_sample(&items, 15, 1);
+        assert_eq!(vec![15, 15, 10], class_counts(&sample).values().cloned().collect::<Vec<_>>());
+        assert_eq!(vec![11, 11, 10], class_counts(&Subsampling::Balanced.apply(&items, 3, 1)).values().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_balanced_keeps_one_per_class() {
+        let items = skewed();
+        // 100 / 50 leaves 2 items to split among 3 classes.
+        assert_eq!(vec![1, 1, 1], class_counts(&Subsampling::Balanced.apply(&items, 50, 1)).values().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_modulo_matches_discard() {
+        let items: Vec<(u8,Image)> = (0..7).map(|i| (i, Image::test_pattern(2, 2))).collect();
+        assert_eq!(discard(&items, 3), Subsampling::Modulo.apply(&items, 3, 99));
+        assert_eq!(3, Subsampling::Random.apply(&items, 3, 99).len());
+        for mode in Subsampling::all() {
+            assert_eq!(Some(mode), Subsampling::from_name(mode.name()));
+        }
+    }
+}
@@ -1,18 +1,15 @@
-use crate::mnist_data::{Image,Grid};
+use crate::mnist_data::{Image, Grid, PixelImage};
 
-fn edge_pixel(img: &Image, x: usize, y: usize) -> u8 {
-    compress(x_total(img, x, y) + y_total(img, x, y))
+fn edge_magnitude(img: &Image, x: usize, y: usize) -> u16 {
+    x_total(img, x, y) + y_total(img, x, y)
 }
 
-fn compress(edge_value: u16) -> u8 {
-    if edge_value > u8::MAX as u16 {
-        u8::MAX
-    } else {
-        edge_value as u8
-    }
+fn x_total(img: &Image, x: usize, y: usize) -> u16 {
+    x_gradient(img, x, y).unsigned_abs()
 }
 
-fn x_total(img: &Image, x: usize, y: usize) -> u16 {
+// Positive where intensity increases to the right.
+fn x_gradient(img: &Image, x: usize, y: usize) -> i16 {
     let mut total = 0;
     total -= get(img, x as isize - 1, y as isize - 1) as i16;
     total -= 2 * get(img, x as isize - 1, y as isize) as i16;
@@ -20,10 +17,15 @@ fn x_total(img: &Image, x: usize, y: usize) -> u16 {
     total += get(img, x as isize + 1, y as isize - 1) as i16;
     total += 2 * get(img, x as isize + 1, y as isize) as i16;
     total += get(img, x as isize + 1, y as isize + 1) as i16;
-    total.abs() as u16
+    total
 }
 
 fn y_total(img: &Image, x: usize, y: usize) -> u16 {
+    y_gradient(img, x, y).unsigned_abs()
+}
+
+// Positive where intensity increases downward.
+fn y_gradient(img: &Image, x: usize, y: usize) -> i16 {
     let mut total = 0;
     total -= get(img, x as isize - 1, y as isize - 1) as i16;
     total -= 2 * get(img, x as isize, y as isize - 1) as i16;
@@ -31,13 +33,54 @@ fn y_total(img: &Image, x: usize, y: usize) -> u16 {
     total += get(img, x as isize - 1, y as isize + 1) as i16;
     total += 2 * get(img, x as isize, y as isize + 1) as i16;
     total += get(img, x as isize + 1, y as isize + 1) as i16;
-    total.abs() as u16
+    total
 }
 
 fn get(img: &Image, x: isize, y: isize) -> u8 {
     img.option_get(x, y).unwrap_or(0)
 }
 
+// Unclipped magnitudes range up to 8 * 255.
+pub fn edge_magnitudes(img: &Image) -> PixelImage<u16> {
+    img.filter(edge_magnitude)
+}
+
 pub fn edge_image(img: &Image) -> Image {
-    img.filter(edge_pixel)
-}
\ No newline at end of file
+    edge_magnitudes(img).clamped_to_u8()
+}
+
+// Signed horizontal and vertical Sobel responses, each ranging over +/- 4 * 255.
+pub fn gradients(img: &Image) -> (PixelImage<f32>, PixelImage<f32>) {
+    (img.filter(|img, x, y| x_gradient(img, x, y) as f32), img.filter(|img, x, y| y_gradient(img, x, y) as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnist_data::ImageIterator;
+    use crate::test_util::{RECT_WIDTH, RECT_HEIGHT};
+
+    #[test]
+    fn test_horizontal_edge() {
+        // Dark above row 10, bright from there down.
+        let mut img = Image::with_width(RECT_WIDTH);
+        for (_, y) in ImageIterator::new(0, 0, RECT_WIDTH, RECT_HEIGHT, 1) {
+            img.add(if y < 10 {0} else {100});
+        }
+        let edges = edge_image(&img);
+        assert_eq!((RECT_WIDTH, RECT_HEIGHT), (edges.width(), edges.height()));
+        assert_eq!(0, edges.get(15, 5));
+        assert_eq!(u8::MAX, edges.get(15, 9));
+        assert_eq!(0, edges.get(15, 15));
+
+        let magnitudes = edge_magnitudes(&img);
+        assert_eq!((RECT_WIDTH, RECT_HEIGHT), (magnitudes.width(), magnitudes.height()));
+        assert_eq!(400, magnitudes.get(15, 9));
+        assert_eq!(400, magnitudes.get(15, 10));
+
+        let (x_gradients, y_gradients) = gradients(&img);
+        assert_eq!(0.0, x_gradients.get(15, 9));
+        assert_eq!(400.0, y_gradients.get(15, 9));
+        assert_eq!(-400.0, gradients(&img.map(|p| 100 - p)).1.get(15, 10));
+    }
+}
@@ -0,0 +1,143 @@
+//! Structural similarity (Wang et al. 2004) and its multi-scale form (Wang, Simoncelli, and Bovik
+//! 2003), turned into dissimilarities by subtracting from 1. Unlike Euclidean distance, these
+//! compare local means, contrasts, and correlations, so they reward matching structure.
+//!
+//! Windows are square and unweighted, taken with `Image::subimage` around every pixel; the parts
+//! of border windows outside the image count as 0.
+
+use crate::mnist_data::{Image, Grid, ImageIterator};
+
+// The stabilizing constants of Wang et al., for 8-bit pixels.
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+pub const SSIM_WINDOW: usize = 7;
+pub const MS_SSIM_SCALES: usize = 3;
+
+// Wang et al.'s weights for five scales, finest first. Fewer scales use the first ones, rescaled
+// to sum to 1.
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+// The luminance term and the combined contrast-structure term for two equally sized windows.
+fn components(window1: &Image, window2: &Image) -> (f64, f64) {
+    assert_eq!((window1.width(), window1.height()), (window2.width(), window2.height()));
+    let n = window1.len() as f64;
+    let pairs = || window1.x_y_iter().map(|(x, y)| (window1.get(x, y) as f64, window2.get(x, y) as f64));
+    let mean1 = pairs().map(|(a, _)| a).sum::<f64>() / n;
+    let mean2 = pairs().map(|(_, b)| b).sum::<f64>() / n;
+    let (mut variance1, mut variance2, mut covariance) = (0.0, 0.0, 0.0);
+    for (a, b) in pairs() {
+        variance1 += (a - mean1) * (a - mean1);
+        variance2 += (b - mean2) * (b - mean2);
+        covariance += (a - mean1) * (b - mean2);
+    }
+    let (variance1, variance2, covariance) = (variance1 / n, variance2 / n, covariance / n);
+    ((2.0 * mean1 * mean2 + C1) / (mean1 * mean1 + mean2 * mean2 + C1),
+     (2.0 * covariance + C2) / (variance1 + variance2 + C2))
+}
+
+// SSIM with the whole of each image as a single window, as for matching small kernels.
+pub fn global_ssim(img1: &Image, img2: &Image) -> f64 {
+    let (luminance, contrast_structure) = components(img1, img2);
+    luminance * contrast_structure
+}
+
+// Means over every window position of the SSIM and of the contrast-structure term.
+fn mean_components(img1: &Image, img2: &Image, side: usize) -> (f64, f64) {
+    assert_eq!((img1.width(), img1.height()), (img2.width(), img2.height()));
+    let positions = ImageIterator::new(0, 0, img1.width(), img1.height(), 1);
+    let (mut ssim_total, mut contrast_total, mut count) = (0.0, 0.0, 0);
+    for (x, y) in positions {
+        let (luminance, contrast_structure) = components(&img1.subimage(x, y, side), &img2.subimage(x, y, side));
+        ssim_total += luminance * contrast_structure;
+        contrast_total += contrast_structure;
+        count += 1;
+    }
+    (ssim_total / count as f64, contrast_total / count as f64)
+}
+
+pub fn ssim(img1: &Image, img2: &Image, side: usize) -> f64 {
+    mean_components(img1, img2, side).0
+}
+
+// Average of each 2x2 block; an odd last row or column is dropped.
+fn halved(img: &Image) -> Image {
+    let mut result = Image::with_width(img.width() / 2);
+    ImageIterator::new(0, 0, img.width() / 2, img.height() / 2, 1).for_each(|(x, y)| {
+        let sum: u16 = [(0, 0), (1, 0), (0, 1), (1, 1)].iter().map(|(dx, dy)| img.get(2 * x + dx, 2 * y + dy) as u16).sum();
+        result.add(((sum + 2) / 4) as u8);
+    });
+    result
+}
+
+// The product over scales of the mean contrast-structure term, with the full SSIM at the coarsest
+// scale, each raised to its weight. Negative terms count as 0, as fractional powers of them are
+// undefined. Windows shrink to fit images that become smaller than side.
+pub fn ms_ssim(img1: &Image, img2: &Image, side: usize, scales: usize) -> f64 {
+    assert!((1..=MS_SSIM_WEIGHTS.len()).contains(&scales), "MS-SSIM supports 1 to {} scales", MS_SSIM_WEIGHTS.len());
+    let total_weight: f64 = MS_SSIM_WEIGHTS[..scales].iter().sum();
+    let (mut current1, mut current2) = (img1.clone(), img2.clone());
+    let mut result = 1.0;
+    for (scale, weight) in MS_SSIM_WEIGHTS[..scales].iter().enumerate() {
+        let window = side.min(current1.width()).min(current1.height()).max(1);
+        let (ssim, contrast_structure) = mean_components(&current1, &current2, window);
+        let term = if scale + 1 == scales {ssim} else {contrast_structure};
+        result *= term.max(0.0).powf(weight / total_weight);
+        if scale + 1 < scales {
+            current1 = halved(&current1);
+            current2 = halved(&current2);
+        }
+    }
+    result
+}
+
+pub fn ssim_dissimilarity(img1: &Image, img2: &Image) -> f64 {
+    1.0 - ssim(img1, img2, SSIM_WINDOW)
+}
+
+pub fn ms_ssim_dissimilarity(img1: &Image, img2: &Image) -> f64 {
+    1.0 - ms_ssim(img1, img2, SSIM_WINDOW, MS_SSIM_SCALES)
+}
+
+pub fn global_ssim_dissimilarity(img1: &Image, img2: &Image) -> f64 {
+    1.0 - global_ssim(img1, img2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(expected: f64, actual: f64) -> bool {
+        (expected - actual).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_identity_and_order() {
+        let img = Image::test_pattern(12, 12);
+        assert!(close(1.0, ssim(&img, &img, SSIM_WINDOW)));
+        assert!(close(1.0, ms_ssim(&img, &img, SSIM_WINDOW, 3)));
+        assert!(close(0.0, global_ssim_dissimilarity(&img, &img)));
+        let slightly = img.map(|p| p.saturating_add(5));
+        let very = img.map(|p| 255 - p);
+        assert!(ssim_dissimilarity(&img, &slightly) < ssim_dissimilarity(&img, &very));
+        assert!(ms_ssim_dissimilarity(&img, &slightly) < ms_ssim_dissimilarity(&img, &very));
+        assert!(close(ssim_dissimilarity(&img, &very), ssim_dissimilarity(&very, &img)));
+    }
+
+    #[test]
+    fn test_global() {
+        let flat = Image::from_slice(&[100; 9], 3, 3);
+        let brighter = Image::from_slice(&[200; 9], 3, 3);
+        let expected_luminance = (2.0 * 100.0 * 200.0 + C1) / (100.0 * 100.0 + 200.0 * 200.0 + C1);
+        assert!(close(expected_luminance, global_ssim(&flat, &brighter)));
+        let edge = Image::from_slice(&[0, 0, 255, 0, 0, 255, 0, 0, 255], 3, 3);
+        let flipped = Image::from_slice(&[255, 0, 0, 255, 0, 0, 255, 0, 0], 3, 3);
+        assert!(global_ssim(&edge, &flipped) < 0.0);
+    }
+
+    #[test]
+    fn test_halved() {
+        let img = Image::from_slice(&[0, 4, 8, 8, 4, 0, 8, 8, 1, 1, 1, 1], 4, 3);
+        assert_eq!(Image::from_slice(&[2, 8], 2, 1), halved(&img));
+    }
+}
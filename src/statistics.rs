@@ -0,0 +1,27 @@
+//! Summary statistics for error rates collected over repeated runs.
+
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// Sample standard deviation (n - 1 in the denominator); zero for fewer than two values.
+pub fn standard_deviation(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(values);
+    (values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() - 1) as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean_and_deviation() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(5.0, mean(&values));
+        assert!((standard_deviation(&values) - 2.138).abs() < 0.001);
+        assert_eq!(0.0, standard_deviation(&[3.0]));
+    }
+}
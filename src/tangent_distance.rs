@@ -0,0 +1,196 @@
+//! Tangent distance (Simard, LeCun, and Denker 1993): the Euclidean distance between two images
+//! once each may move along the tangents of small transformations, so that slight shifts,
+//! rotations, and stroke-width changes cost little.
+//!
+//! Tangents come from the gradients of a Gaussian-smoothed copy of the image, and are
+//! orthonormalized when the image is converted, so each training image's tangents are computed
+//! only once. Displacements along the tangents are unconstrained.
+
+use std::io;
+use std::io::{Read, Write};
+use crate::augmentation::gaussian_smoothed;
+use crate::feature_cache::Cacheable;
+use crate::mnist_data::{Image, Grid};
+
+// Smoothing before differentiating, in pixels.
+pub const TANGENT_SIGMA: f64 = 0.9;
+
+// Tangents shorter than this, relative to the image, add nothing once orthonormalized.
+const NEGLIGIBLE: f64 = 1e-9;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tangent {
+    XTranslation, YTranslation, Rotation, Scaling,
+    // Stretching along one axis while compressing along the other.
+    AxisDeformation,
+    // The same, along the diagonals.
+    Shear,
+    Thickness
+}
+
+impl Tangent {
+    pub fn all() -> Vec<Tangent> {
+        vec![Tangent::XTranslation, Tangent::YTranslation, Tangent::Rotation, Tangent::Scaling,
+             Tangent::AxisDeformation, Tangent::Shear, Tangent::Thickness]
+    }
+
+    // The change in the pixel at (x, y), relative to the image center, with smoothed gradient (gx, gy).
+    fn value(&self, x: f64, y: f64, gx: f64, gy: f64) -> f64 {
+        match self {
+            Tangent::XTranslation => gx,
+            Tangent::YTranslation => gy,
+            Tangent::Rotation => y * gx - x * gy,
+            Tangent::Scaling => x * gx + y * gy,
+            Tangent::AxisDeformation => x * gx - y * gy,
+            Tangent::Shear => y * gx + x * gy,
+            Tangent::Thickness => gx * gx + gy * gy
+        }
+    }
+}
+
+// Pixel values, in row-major order, with an orthonormal basis for their tangent plane.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TangentImage {
+    pixels: Vec<f32>,
+    tangents: Vec<Vec<f32>>
+}
+
+impl TangentImage {
+    pub fn new(img: &Image) -> Self {
+        TangentImage::with_tangents(img, &Tangent::all(), TANGENT_SIGMA)
+    }
+
+    pub fn with_tangents(img: &Image, kinds: &[Tangent], sigma: f64) -> Self {
+        let (width, height) = (img.width(), img.height());
+        let values: Vec<f64> = img.pixels().iter().map(|p| *p as f64).collect();
+        let smoothed = gaussian_smoothed(&values, width, height, sigma);
+        let at = |x: isize, y: isize| if x < 0 || y < 0 || x >= width as isize || y >= height as isize {0.0} else {smoothed[y as usize * width + x as usize]};
+        let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+        let raw: Vec<Vec<f64>> = kinds.iter().map(|kind| {
+            img.x_y_iter().map(|(x, y)| {
+                let (xi, yi) = (x as isize, y as isize);
+                let gx = (at(xi + 1, yi) - at(xi - 1, yi)) / 2.0;
+                let gy = (at(xi, yi + 1) - at(xi, yi - 1)) / 2.0;
+                kind.value(x as f64 - cx, y as f64 - cy, gx, gy)
+            }).collect()
+        }).collect();
+        let tangents = orthonormalized(&raw).iter().map(|t| t.iter().map(|v| *v as f32).collect()).collect();
+        TangentImage {pixels: values.iter().map(|v| *v as f32).collect(), tangents}
+    }
+
+    pub fn num_tangents(&self) -> usize {
+        self.tangents.len()
+    }
+}
+
+impl Cacheable for TangentImage {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.pixels.write_to(output)?;
+        self.tangents.write_to(output)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(TangentImage {pixels: Vec::read_from(input)?, tangents: Vec::read_from(input)?})
+    }
+}
+
+fn dot<A: Copy + Into<f64>, B: Copy + Into<f64>>(a: &[A], b: &[B]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (*x).into() * (*y).into()).sum()
+}
+
+// Modified Gram-Schmidt, dropping vectors that are (nearly) in the span of those before them.
+fn orthonormalized<V: AsRef<[f64]>>(vectors: &[V]) -> Vec<Vec<f64>> {
+    let mut basis: Vec<Vec<f64>> = Vec::new();
+    for v in vectors.iter() {
+        let v = v.as_ref();
+        let mut w = v.to_vec();
+        for b in basis.iter() {
+            let projection = dot(&w, b);
+            w.iter_mut().zip(b.iter()).for_each(|(wi, bi)| *wi -= projection * bi);
+        }
+        let norm = dot(&w, &w).sqrt();
+        if norm > NEGLIGIBLE * dot(v, v).sqrt().max(1.0) {
+            basis.push(w.iter().map(|wi| wi / norm).collect());
+        }
+    }
+    basis
+}
+
+// Squared length of what remains of difference after removing its components along basis.
+fn residual(difference: &[f64], basis: &[Vec<f64>]) -> f64 {
+    let projected: f64 = basis.iter().map(|b| dot(difference, b).powi(2)).sum();
+    (dot(difference, difference) - projected).max(0.0)
+}
+
+fn difference(t1: &TangentImage, t2: &TangentImage) -> Vec<f64> {
+    assert_eq!(t1.pixels.len(), t2.pixels.len());
+    t1.pixels.iter().zip(t2.pixels.iter()).map(|(a, b)| *a as f64 - *b as f64).collect()
+}
+
+// Squared distance from t2 to the tangent plane of t1; only t1's tangents are used.
+pub fn one_sided_tangent_distance(t1: &TangentImage, t2: &TangentImage) -> f64 {
+    let basis: Vec<Vec<f64>> = t1.tangents.iter().map(|t| t.iter().map(|v| *v as f64).collect()).collect();
+    residual(&difference(t1, t2), &basis)
+}
+
+// Squared distance between the two tangent planes. Their combined tangents are orthonormalized for
+// each pair, so this costs roughly (number of tangents)^2 times as much as a Euclidean distance.
+pub fn tangent_distance(t1: &TangentImage, t2: &TangentImage) -> f64 {
+    let combined: Vec<Vec<f64>> = t1.tangents.iter().chain(t2.tangents.iter())
+        .map(|t| t.iter().map(|v| *v as f64).collect())
+        .collect();
+    residual(&difference(t1, t2), &orthonormalized(&combined))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::augmentation::{shifted, rotated};
+    use crate::euclidean_distance::euclidean_distance;
+
+    // A soft blob, so that small transformations stay close to the tangent plane.
+    fn blob() -> Image {
+        let pixels: Vec<u8> = (0..16 * 16).map(|i| {
+            let (x, y) = ((i % 16) as f64 - 7.0, (i / 16) as f64 - 8.0);
+            (220.0 * (-(x * x / 8.0 + y * y / 4.0)).exp()) as u8
+        }).collect();
+        Image::from_slice(&pixels, 16, 16)
+    }
+
+    #[test]
+    fn test_orthonormal() {
+        let t = TangentImage::new(&blob());
+        assert_eq!(Tangent::all().len(), t.num_tangents());
+        for (i, a) in t.tangents.iter().enumerate() {
+            for (j, b) in t.tangents.iter().enumerate() {
+                let expected = if i == j {1.0} else {0.0};
+                assert!((dot(a, b) - expected).abs() < 1e-4);
+            }
+        }
+        let blank = TangentImage::new(&Image::from_slice(&[0; 16], 4, 4));
+        assert_eq!(0, blank.num_tangents());
+    }
+
+    #[test]
+    fn test_invariance() {
+        let img = blob();
+        for moved in [shifted(&img, 0.5, 0.0), rotated(&img, 5.0)] {
+            let (t1, t2) = (TangentImage::new(&img), TangentImage::new(&moved));
+            let euclidean = euclidean_distance(&img, &moved) as f64;
+            let one_sided = one_sided_tangent_distance(&t1, &t2);
+            let two_sided = tangent_distance(&t1, &t2);
+            assert!(one_sided < euclidean / 4.0);
+            assert!(two_sided <= one_sided + 1e-6);
+        }
+        let t = TangentImage::new(&img);
+        assert!(tangent_distance(&t, &t).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let t = TangentImage::new(&blob());
+        let mut bytes = Vec::new();
+        t.write_to(&mut bytes).unwrap();
+        assert_eq!(t, TangentImage::read_from(&mut bytes.as_slice()).unwrap());
+    }
+}
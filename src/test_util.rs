@@ -0,0 +1,18 @@
+//! Fixtures shared by the unit tests of several modules.
+
+use std::env;
+use std::path::PathBuf;
+use crate::mnist_data::Image;
+
+// Wider than tall, so that tests catch code that swaps width and height or assumes they are equal.
+pub const RECT_WIDTH: usize = 32;
+pub const RECT_HEIGHT: usize = 20;
+
+pub fn rectangular_image() -> Image {
+    Image::test_pattern(RECT_WIDTH, RECT_HEIGHT)
+}
+
+// A path under the system temporary directory that no other test or concurrent test run shares.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("distance_research_{}_{}", name, std::process::id()))
+}
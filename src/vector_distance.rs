@@ -0,0 +1,155 @@
+//! Distances that treat an `Image` as a vector of pixel values, as alternatives to
+//! `euclidean_distance`. Integer-valued ones return `u32` and the rest `f64`, so every one works
+//! both for kNN and as a `kmeans` distance.
+//!
+//! ```
+//! use distance_research::vector_distance::{manhattan_distance, chebyshev_distance};
+//! use distance_research::mnist_data::Image;
+//!
+//! let img1 = Image::from_vec(&vec![1, 2, 3, 4]);
+//! let img2 = Image::from_vec(&vec![4, 3, 2, 1]);
+//! assert_eq!(3 + 1 + 1 + 3, manhattan_distance(&img1, &img2));
+//! assert_eq!(3, chebyshev_distance(&img1, &img2));
+//! ```
+
+use crate::mnist_data::{Grid, Image};
+
+fn pixel_pairs<'a>(img1: &'a Image, img2: &'a Image) -> impl Iterator<Item=(f64, f64)> + 'a {
+    assert_eq!(img1.width(), img2.width());
+    assert_eq!(img1.height(), img2.height());
+    img1.pixels().iter().zip(img2.pixels().iter()).map(|(a, b)| (*a as f64, *b as f64))
+}
+
+pub fn manhattan_distance(img1: &Image, img2: &Image) -> u32 {
+    pixel_pairs(img1, img2).map(|(a, b)| (a - b).abs() as u32).sum()
+}
+
+pub fn chebyshev_distance(img1: &Image, img2: &Image) -> u32 {
+    pixel_pairs(img1, img2).map(|(a, b)| (a - b).abs() as u32).max().unwrap_or(0)
+}
+
+// The p-th root of the sum of p-th powers of the differences; p = 1 and 2 are Manhattan and
+// (unsquared) Euclidean.
+pub fn minkowski_distance(img1: &Image, img2: &Image, p: f64) -> f64 {
+    assert!(p >= 1.0, "Minkowski distance needs p >= 1");
+    pixel_pairs(img1, img2).map(|(a, b)| (a - b).abs().powf(p)).sum::<f64>().powf(1.0 / p)
+}
+
+// 1 - cosine similarity. A blank image is at distance 1 from any other, and 0 from itself.
+pub fn cosine_distance(img1: &Image, img2: &Image) -> f64 {
+    let (mut dot, mut norm1, mut norm2) = (0.0, 0.0, 0.0);
+    for (a, b) in pixel_pairs(img1, img2) {
+        dot += a * b;
+        norm1 += a * a;
+        norm2 += b * b;
+    }
+    if norm1 == 0.0 || norm2 == 0.0 {
+        if norm1 == norm2 {0.0} else {1.0}
+    } else {
+        1.0 - dot / (norm1 * norm2).sqrt()
+    }
+}
+
+// 1 - Pearson correlation, from 0 to 2. An image of one uniform value correlates with nothing,
+// so it is at distance 1 from any image but an identical one.
+pub fn pearson_distance(img1: &Image, img2: &Image) -> f64 {
+    let n = img1.len() as f64;
+    let mean1 = pixel_pairs(img1, img2).map(|(a, _)| a).sum::<f64>() / n;
+    let mean2 = pixel_pairs(img1, img2).map(|(_, b)| b).sum::<f64>() / n;
+    let (mut covariance, mut variance1, mut variance2) = (0.0, 0.0, 0.0);
+    for (a, b) in pixel_pairs(img1, img2) {
+        covariance += (a - mean1) * (b - mean2);
+        variance1 += (a - mean1) * (a - mean1);
+        variance2 += (b - mean2) * (b - mean2);
+    }
+    if variance1 == 0.0 || variance2 == 0.0 {
+        if img1 == img2 {0.0} else {1.0}
+    } else {
+        1.0 - covariance / (variance1 * variance2).sqrt()
+    }
+}
+
+// Half the sum of (a - b)^2 / (a + b), skipping pixels that are 0 in both images.
+pub fn chi_squared_distance(img1: &Image, img2: &Image) -> f64 {
+    pixel_pairs(img1, img2)
+        .filter(|(a, b)| a + b > 0.0)
+        .map(|(a, b)| (a - b) * (a - b) / (a + b))
+        .sum::<f64>() / 2.0
+}
+
+// 1 - the sum of pixelwise minimums over the larger image total, treating each image as a
+// histogram of intensity mass. Two blank images are at distance 0.
+pub fn histogram_intersection_distance(img1: &Image, img2: &Image) -> f64 {
+    let (mut overlap, mut total1, mut total2) = (0.0, 0.0, 0.0);
+    for (a, b) in pixel_pairs(img1, img2) {
+        overlap += a.min(b);
+        total1 += a;
+        total2 += b;
+    }
+    let total = f64::max(total1, total2);
+    if total == 0.0 {0.0} else {1.0 - overlap / total}
+}
+
+// The sum of |a - b| / (a + b), skipping pixels that are 0 in both images.
+pub fn canberra_distance(img1: &Image, img2: &Image) -> f64 {
+    pixel_pairs(img1, img2)
+        .filter(|(a, b)| a + b > 0.0)
+        .map(|(a, b)| (a - b).abs() / (a + b))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::euclidean_distance::euclidean_distance;
+    use crate::mnist_data::image_mean;
+
+    fn close(expected: f64, actual: f64) -> bool {
+        (expected - actual).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_norms() {
+        let img1 = Image::from_vec(&vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let img2 = Image::from_vec(&vec![9, 8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(2 * (8 + 6 + 4 + 2), manhattan_distance(&img1, &img2));
+        assert_eq!(8, chebyshev_distance(&img1, &img2));
+        assert!(close(manhattan_distance(&img1, &img2) as f64, minkowski_distance(&img1, &img2, 1.0)));
+        assert!(close((euclidean_distance(&img1, &img2) as f64).sqrt(), minkowski_distance(&img1, &img2, 2.0)));
+        assert!(minkowski_distance(&img1, &img2, 8.0) > chebyshev_distance(&img1, &img2) as f64);
+    }
+
+    #[test]
+    fn test_similarities() {
+        let img = Image::from_vec(&vec![0, 10, 20, 30]);
+        let doubled = Image::from_vec(&vec![0, 20, 40, 60]);
+        let reversed = Image::from_vec(&vec![30, 20, 10, 0]);
+        let blank = Image::from_vec(&vec![0, 0, 0, 0]);
+        assert!(close(0.0, cosine_distance(&img, &doubled)));
+        assert!(close(1.0, cosine_distance(&img, &blank)));
+        assert!(close(0.0, cosine_distance(&blank, &blank)));
+        assert!(close(0.0, pearson_distance(&img, &doubled)));
+        assert!(close(2.0, pearson_distance(&img, &reversed)));
+        assert!(close(1.0, pearson_distance(&img, &blank)));
+        assert!(close(0.0, pearson_distance(&blank, &blank)));
+    }
+
+    #[test]
+    fn test_histogram_distances() {
+        let img1 = Image::from_vec(&vec![0, 10, 20, 30]);
+        let img2 = Image::from_vec(&vec![0, 30, 20, 10]);
+        assert!(close((400.0 / 40.0 + 400.0 / 40.0) / 2.0, chi_squared_distance(&img1, &img2)));
+        assert!(close(1.0 - 40.0 / 60.0, histogram_intersection_distance(&img1, &img2)));
+        assert!(close(20.0 / 40.0 + 20.0 / 40.0, canberra_distance(&img1, &img2)));
+        for distance in [chi_squared_distance, histogram_intersection_distance, canberra_distance] {
+            assert!(close(0.0, distance(&img1, &img1)));
+        }
+    }
+
+    #[test]
+    fn test_kmeans() {
+        let images: Vec<Image> = (0..6).map(|i| Image::from_vec(&vec![i * 40, 0, 0, 255 - i * 40])).collect();
+        assert_eq!(2, kmeans::Kmeans::new(2, &images, manhattan_distance, image_mean).move_means().len());
+        assert_eq!(2, kmeans::Kmeans::new(2, &images, cosine_distance, image_mean).move_means().len());
+    }
+}
@@ -0,0 +1,221 @@
+//! Earth mover's (Wasserstein-1) distances between images treated as distributions of intensity
+//! mass over the pixel grid, with Euclidean ground distance. Where `kernel_points` matches a few
+//! chosen points, these move all of the ink, so a stroke that shifts slightly costs little.
+//!
+//! The Sinkhorn distance (Cuturi 2013) approximates the exact distance with an entropy-regularized
+//! transport plan; it slightly overestimates it, and is not quite 0 between identical images.
+//! Its ground costs depend only on the offset between two pixels, so they are tabulated once for
+//! the image size. Even so, each pair of images costs iterations x (inked pixels)^2 steps, which
+//! suits subsampled data rather than full kNN runs.
+//! The sliced distance averages exact one-dimensional distances between projections of the two
+//! distributions onto evenly spaced directions, which is far faster. Its projections are sorted
+//! once, when the image is converted.
+
+use std::io;
+use std::io::{Read, Write};
+use crate::feature_cache::Cacheable;
+use crate::mnist_data::{Image, Grid};
+
+pub const SINKHORN_EPSILON: f64 = 1.0;
+pub const SINKHORN_ITERATIONS: usize = 100;
+pub const NUM_SLICES: usize = 16;
+
+// The nonzero pixels of an image, with weights summing to 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MassDistribution {
+    width: usize,
+    height: usize,
+    points: Vec<(usize,usize)>,
+    weights: Vec<f32>,
+    // For each direction, (position along it, weight) of every point, sorted by position.
+    projections: Vec<Vec<(f32,f32)>>
+}
+
+impl MassDistribution {
+    pub fn new(img: &Image, num_slices: usize) -> Self {
+        let points: Vec<(usize,usize)> = img.x_y_iter().filter(|(x, y)| img.get(*x, *y) > 0).collect();
+        let total: f64 = points.iter().map(|(x, y)| img.get(*x, *y) as f64).sum();
+        let weights: Vec<f32> = points.iter().map(|(x, y)| (img.get(*x, *y) as f64 / total) as f32).collect();
+        let projections = (0..num_slices).map(|slice| {
+            let (sin, cos) = (std::f64::consts::PI * slice as f64 / num_slices as f64).sin_cos();
+            let mut projected: Vec<(f32,f32)> = points.iter().zip(weights.iter())
+                .map(|((x, y), weight)| ((*x as f64 * cos + *y as f64 * sin) as f32, *weight))
+                .collect();
+            projected.sort_by(|(p1, _), (p2, _)| p1.total_cmp(p2));
+            projected
+        }).collect();
+        MassDistribution {width: img.width(), height: img.height(), points, weights, projections}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    // Blank images have no distribution. Two of them are at distance 0; a blank image and any
+    // other are as far apart as the image's diagonal, the most any mass could move.
+    fn distance_if_blank(&self, other: &MassDistribution) -> Option<f64> {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+        match (self.is_empty(), other.is_empty()) {
+            (false, false) => None,
+            (true, true) => Some(0.0),
+            _ => Some(((self.width * self.width + self.height * self.height) as f64).sqrt())
+        }
+    }
+}
+
+impl Cacheable for MassDistribution {
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.width.write_to(output)?;
+        self.height.write_to(output)?;
+        self.points.write_to(output)?;
+        self.weights.write_to(output)?;
+        self.projections.write_to(output)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(MassDistribution {width: usize::read_from(input)?, height: usize::read_from(input)?, points: Vec::read_from(input)?,
+            weights: Vec::read_from(input)?, projections: Vec::read_from(input)?})
+    }
+}
+
+// The Euclidean ground distance d and the Sinkhorn kernel exp(-d / epsilon) for every offset
+// (|dx|, |dy|) between two pixels of a width x height image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SinkhornKernel {
+    width: usize,
+    height: usize,
+    distances: Vec<f64>,
+    kernel: Vec<f64>
+}
+
+impl SinkhornKernel {
+    // Smaller epsilon approaches the exact distance but needs more iterations to converge.
+    pub fn new(width: usize, height: usize, epsilon: f64) -> Self {
+        let distances: Vec<f64> = (0..height).flat_map(|dy| (0..width).map(move |dx| ((dx * dx + dy * dy) as f64).sqrt())).collect();
+        let kernel = distances.iter().map(|d| (-d / epsilon).exp()).collect();
+        SinkhornKernel {width, height, distances, kernel}
+    }
+
+    fn offset(&self, p1: (usize,usize), p2: (usize,usize)) -> usize {
+        p1.1.abs_diff(p2.1) * self.width + p1.0.abs_diff(p2.0)
+    }
+}
+
+pub fn sinkhorn_distance(m1: &MassDistribution, m2: &MassDistribution, kernel: &SinkhornKernel, iterations: usize) -> f64 {
+    if let Some(distance) = m1.distance_if_blank(m2) {
+        return distance;
+    }
+    assert_eq!((m1.width, m1.height), (kernel.width, kernel.height));
+    let (n, m) = (m1.points.len(), m2.points.len());
+    let offsets: Vec<usize> = m1.points.iter().flat_map(|p1| m2.points.iter().map(|p2| kernel.offset(*p1, *p2))).collect();
+    let (mut u, mut v) = (vec![1.0; n], vec![1.0; m]);
+    for _ in 0..iterations {
+        for i in 0..n {
+            let kv: f64 = (0..m).map(|j| kernel.kernel[offsets[i * m + j]] * v[j]).sum();
+            u[i] = m1.weights[i] as f64 / kv.max(f64::MIN_POSITIVE);
+        }
+        for j in 0..m {
+            let ku: f64 = (0..n).map(|i| kernel.kernel[offsets[i * m + j]] * u[i]).sum();
+            v[j] = m2.weights[j] as f64 / ku.max(f64::MIN_POSITIVE);
+        }
+    }
+    (0..n).flat_map(|i| (0..m).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let offset = offsets[i * m + j];
+            u[i] * kernel.kernel[offset] * v[j] * kernel.distances[offset]
+        })
+        .sum()
+}
+
+// Exact Wasserstein-1 distance between two sorted one-dimensional distributions of equal mass:
+// the area between their cumulative distributions.
+fn wasserstein_1d(d1: &[(f32,f32)], d2: &[(f32,f32)]) -> f64 {
+    let (mut i, mut j) = (0, 0);
+    let (mut difference, mut total) = (0.0, 0.0);
+    let mut previous: Option<f64> = None;
+    while i < d1.len() || j < d2.len() {
+        let take_first = j == d2.len() || (i < d1.len() && d1[i].0 <= d2[j].0);
+        let (position, change) = if take_first {
+            i += 1;
+            (d1[i - 1].0 as f64, d1[i - 1].1 as f64)
+        } else {
+            j += 1;
+            (d2[j - 1].0 as f64, -d2[j - 1].1 as f64)
+        };
+        if let Some(previous) = previous {
+            total += f64::abs(difference) * (position - previous);
+        }
+        difference += change;
+        previous = Some(position);
+    }
+    total
+}
+
+pub fn sliced_wasserstein_distance(m1: &MassDistribution, m2: &MassDistribution) -> f64 {
+    if let Some(distance) = m1.distance_if_blank(m2) {
+        return distance;
+    }
+    assert_eq!(m1.projections.len(), m2.projections.len());
+    m1.projections.iter().zip(m2.projections.iter())
+        .map(|(p1, p2)| wasserstein_1d(p1, p2))
+        .sum::<f64>() / m1.projections.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dots(points: &[(usize,usize)]) -> Image {
+        let mut pixels = vec![0; 100];
+        points.iter().for_each(|(x, y)| pixels[y * 10 + x] = 100);
+        Image::from_slice(&pixels, 10, 10)
+    }
+
+    #[test]
+    fn test_sinkhorn() {
+        let kernel = SinkhornKernel::new(10, 10, SINKHORN_EPSILON);
+        let one = MassDistribution::new(&dots(&[(2, 2)]), NUM_SLICES);
+        let moved = MassDistribution::new(&dots(&[(5, 6)]), NUM_SLICES);
+        assert!((5.0 - sinkhorn_distance(&one, &moved, &kernel, SINKHORN_ITERATIONS)).abs() < 1e-6);
+        let sharp_kernel = SinkhornKernel::new(10, 10, 0.2);
+        let pair = MassDistribution::new(&dots(&[(1, 1), (8, 1)]), NUM_SLICES);
+        let lower = MassDistribution::new(&dots(&[(1, 3), (8, 3)]), NUM_SLICES);
+        let sharp = sinkhorn_distance(&pair, &lower, &sharp_kernel, 200);
+        assert!((2.0 - sharp).abs() < 1e-3);
+        assert!(sinkhorn_distance(&pair, &pair, &sharp_kernel, 200) < 1e-3);
+    }
+
+    #[test]
+    fn test_kernel_table() {
+        let kernel = SinkhornKernel::new(10, 6, 2.0);
+        let offset = kernel.offset((7, 1), (4, 5));
+        assert_eq!(5.0, kernel.distances[offset]);
+        assert_eq!((-2.5f64).exp(), kernel.kernel[offset]);
+        assert_eq!(offset, kernel.offset((4, 5), (7, 1)));
+    }
+
+    #[test]
+    fn test_sliced() {
+        let img = dots(&[(1, 1), (2, 1), (3, 4)]);
+        let shifted = |dx: usize| dots(&[(1 + dx, 1), (2 + dx, 1), (3 + dx, 4)]);
+        let m = MassDistribution::new(&img, NUM_SLICES);
+        assert_eq!(0.0, sliced_wasserstein_distance(&m, &m));
+        let near = sliced_wasserstein_distance(&m, &MassDistribution::new(&shifted(2), NUM_SLICES));
+        let far = sliced_wasserstein_distance(&m, &MassDistribution::new(&shifted(4), NUM_SLICES));
+        assert!(near > 0.0);
+        assert!((2.0 * near - far).abs() < 1e-4);
+        assert!(far <= 4.0);
+        assert_eq!(3.0, wasserstein_1d(&[(0.0, 0.5), (1.0, 0.5)], &[(3.0, 0.5), (4.0, 0.5)]));
+    }
+
+    #[test]
+    fn test_blank_and_cache() {
+        let blank = MassDistribution::new(&dots(&[]), NUM_SLICES);
+        let dot = MassDistribution::new(&dots(&[(0, 0)]), NUM_SLICES);
+        assert_eq!(0.0, sliced_wasserstein_distance(&blank, &blank));
+        assert_eq!(200.0f64.sqrt(), sinkhorn_distance(&blank, &dot, &SinkhornKernel::new(10, 10, SINKHORN_EPSILON), SINKHORN_ITERATIONS));
+        let mut bytes = Vec::new();
+        dot.write_to(&mut bytes).unwrap();
+        assert_eq!(dot, MassDistribution::read_from(&mut bytes.as_slice()).unwrap());
+    }
+}